@@ -1,25 +1,51 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+
 use anyhow::Result;
-use fuscum::fingerprint::WithFingerprint;
+use fuscum::fingerprint::{FunctionFingerPrint, Metric};
 use rayon::prelude::*;
 
-use crate::discovery::Submission;
-use crate::summary::{PairSummary, Summaries, Summary};
+use crate::discovery::{SourceFile, Submission};
+use crate::summary::{FunctionPair, FunctionSummary, PairSummary, Summaries, Summary};
 
 pub struct SimilarityAnalyzer {
     threshold: f32,
     top_k: usize,
+    metric: Metric,
+    /// whether submissions of different languages are compared
+    cross_lang: bool,
 }
 
 impl SimilarityAnalyzer {
-    pub fn new(threshold: f32, top_k: usize) -> Self {
-        Self { threshold, top_k }
+    pub fn new(threshold: f32, top_k: usize, metric: Metric, cross_lang: bool) -> Self {
+        Self {
+            threshold,
+            top_k,
+            metric,
+            cross_lang,
+        }
+    }
+
+    /// Whether a submission is scored against another one
+    fn compares(&self, base: &Submission, other: &Submission) -> bool {
+        !std::ptr::eq(other, base) && (self.cross_lang || !base.is_cross_lang(other))
     }
 
     pub fn analyze_fingerprints(&self, submissions: &[Submission]) -> Result<Summaries> {
-        let mut summaries: Vec<Summary> = submissions
+        self.analyze_against(submissions, submissions)
+    }
+
+    /// Compare every submission of `bases` against those of `others` only
+    pub fn analyze_against(
+        &self,
+        bases: &[Submission],
+        others: &[Submission],
+    ) -> Result<Summaries> {
+        let index = InvertedIndex::new(others.iter().map(Submission::hashes));
+        let mut summaries: Vec<Summary> = bases
             .par_iter()
             .map(|base| {
-                let pairs = self.compute_pair_summaries(base, submissions);
+                let pairs = self.compute_pair_summaries(base, others, &index);
                 let max_score = pairs.first().map(|p| p.score).unwrap_or(0.0);
 
                 Summary {
@@ -30,24 +56,369 @@ impl SimilarityAnalyzer {
             })
             .collect();
 
-        summaries.sort_by(|a, b| b.max_score.partial_cmp(&a.max_score).unwrap());
+        summaries.sort_by(|a, b| b.max_score.total_cmp(&a.max_score));
         summaries.retain(|s| s.max_score >= self.threshold);
 
         Ok(summaries.into())
     }
 
-    fn compute_pair_summaries(&self, base: &Submission, others: &[Submission]) -> Vec<PairSummary> {
-        let mut pairs: Vec<PairSummary> = others
-            .iter()
-            .filter(|other| other.name != base.name)
-            .map(|other| PairSummary {
-                against: other.name.clone(),
-                score: base.fingerprint.similarity(&other.fingerprint),
+    fn compute_pair_summaries(
+        &self,
+        base: &Submission,
+        others: &[Submission],
+        index: &InvertedIndex,
+    ) -> Vec<PairSummary> {
+        let hashes = base.hashes();
+        let shared = index.shared_counts(&hashes);
+
+        let mut pairs: Vec<(usize, f32)> = shared
+            .into_iter()
+            .filter(|&(other, _)| self.compares(base, &others[other]))
+            .map(|(other, count)| {
+                let score = self.metric.score(hashes.len(), index.sizes[other], count);
+                (other, score)
             })
             .collect();
-
-        pairs.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap());
+        pairs.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
         pairs.truncate(self.top_k);
+
+        // submissions sharing nothing with the base score zero,
+        // and fill up the remaining slots in their original order
+        if pairs.len() < self.top_k {
+            let listed: HashSet<usize> = pairs.iter().map(|&(other, _)| other).collect();
+            let padding = (0..others.len())
+                .filter(|other| !listed.contains(other))
+                .filter(|&other| self.compares(base, &others[other]))
+                .take(self.top_k - pairs.len())
+                .map(|other| (other, 0.0))
+                .collect::<Vec<_>>();
+            pairs.extend(padding);
+        }
+
         pairs
+            .into_iter()
+            .map(|(other, score)| PairSummary {
+                against: others[other].name.clone(),
+                score,
+            })
+            .collect()
+    }
+
+    /// Find the best matching function of the other submissions for every
+    /// function of `bases`, keeping pairs scoring at least the threshold, best first
+    pub fn analyze_functions(
+        &self,
+        bases: &[Submission],
+        others: &[Submission],
+    ) -> Vec<FunctionPair> {
+        let candidates: Vec<_> = functions(others).collect();
+        let index = InvertedIndex::new(
+            candidates
+                .iter()
+                .map(|(_, _, function)| Cow::Borrowed(function.fingerprint.hashes())),
+        );
+
+        let mut pairs: Vec<FunctionPair> = bases
+            .par_iter()
+            .flat_map_iter(|base| functions(std::slice::from_ref(base)))
+            .filter_map(|(base, file, function)| {
+                let hashes = function.fingerprint.hashes();
+                let (other, score) = index
+                    .shared_counts(hashes)
+                    .into_iter()
+                    .filter(|&(other, _)| self.compares(base, candidates[other].0))
+                    .map(|(other, count)| {
+                        (
+                            other,
+                            self.metric.score(hashes.len(), index.sizes[other], count),
+                        )
+                    })
+                    .max_by(|a, b| a.1.total_cmp(&b.1).then(b.0.cmp(&a.0)))?;
+                let (other, other_file, other_function) = candidates[other];
+                (score >= self.threshold).then(|| FunctionPair {
+                    base: function_summary(base, file, function),
+                    against: function_summary(other, other_file, other_function),
+                    score,
+                })
+            })
+            .collect();
+        pairs.sort_by(|a, b| b.score.total_cmp(&a.score));
+
+        // comparing a batch against itself finds most pairs in both directions
+        let mut seen = HashSet::new();
+        pairs.retain(|pair| {
+            let key = |f: &FunctionSummary| (f.submission.clone(), f.file.clone(), f.start_line);
+            seen.insert((key(&pair.base), key(&pair.against)))
+                && !seen.contains(&(key(&pair.against), key(&pair.base)))
+        });
+        pairs
+    }
+}
+
+/// Every function of the submissions, along with where it comes from
+fn functions(
+    submissions: &[Submission],
+) -> impl Iterator<Item = (&Submission, &SourceFile, &FunctionFingerPrint)> {
+    submissions.iter().flat_map(|submission| {
+        submission.files.iter().flat_map(move |file| {
+            file.functions
+                .iter()
+                .map(move |function| (submission, file, function))
+        })
+    })
+}
+
+fn function_summary(
+    submission: &Submission,
+    file: &SourceFile,
+    function: &FunctionFingerPrint,
+) -> FunctionSummary {
+    FunctionSummary {
+        submission: submission.name.clone(),
+        file: file.name.clone(),
+        name: function.name.clone(),
+        start_line: function.location.start.line,
+        end_line: function.location.end.line,
+    }
+}
+
+/// Maps every hash to the submissions (or functions) containing it, so the
+/// hashes shared with a submission are counted by walking posting lists
+/// instead of intersecting its fingerprint with every other one
+struct InvertedIndex {
+    /// the number of distinct hashes of each submission
+    sizes: Vec<usize>,
+    postings: HashMap<u64, Vec<usize>>,
+}
+
+impl InvertedIndex {
+    /// Index the distinct hashes of each submission
+    fn new<'h>(hashes: impl Iterator<Item = Cow<'h, [u64]>>) -> Self {
+        let mut sizes = Vec::new();
+        let mut postings: HashMap<u64, Vec<usize>> = HashMap::new();
+        for (i, hashes) in hashes.enumerate() {
+            for &hash in hashes.iter() {
+                postings.entry(hash).or_default().push(i);
+            }
+            sizes.push(hashes.len());
+        }
+
+        Self { sizes, postings }
+    }
+
+    /// The number of hashes shared with each submission sharing at least one
+    fn shared_counts(&self, hashes: &[u64]) -> HashMap<usize, usize> {
+        let mut counts: HashMap<usize, usize> = HashMap::new();
+        for posting in hashes.iter().filter_map(|hash| self.postings.get(hash)) {
+            for &other in posting {
+                *counts.entry(other).or_default() += 1;
+            }
+        }
+        counts
+    }
+}
+
+/// Drops hashes shared by too many submissions, which are likely to be
+/// boilerplate rather than evidence of copying
+pub struct FrequencyFilter {
+    max_submissions: Option<usize>,
+    max_fraction: Option<f32>,
+}
+
+pub struct FilterReport {
+    /// the largest number of submissions a hash could appear in and be kept
+    pub limit: usize,
+    /// the number of distinct hashes discarded
+    pub discarded_hashes: usize,
+    /// the number of fingerprints removed across all submissions
+    pub removed_fingerprints: usize,
+}
+
+impl FrequencyFilter {
+    pub fn new(max_submissions: Option<usize>, max_fraction: Option<f32>) -> Self {
+        Self {
+            max_submissions,
+            max_fraction,
+        }
+    }
+
+    /// The largest number of submissions a hash may appear in, if any limit is set
+    fn limit(&self, total: usize) -> Option<usize> {
+        let by_fraction = self
+            .max_fraction
+            .map(|fraction| (fraction * total as f32).floor() as usize);
+        match (self.max_submissions, by_fraction) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    pub fn apply(&self, submissions: &mut [Submission]) -> Option<FilterReport> {
+        let limit = self.limit(submissions.len())?;
+
+        let mut frequency: HashMap<u64, usize> = HashMap::new();
+        for submission in submissions.iter() {
+            for &hash in submission.hashes().iter() {
+                *frequency.entry(hash).or_default() += 1;
+            }
+        }
+        let common: HashSet<u64> = frequency
+            .into_iter()
+            .filter(|&(_, count)| count > limit)
+            .map(|(hash, _)| hash)
+            .collect();
+
+        let removed_fingerprints = submissions.par_iter_mut().map(|s| s.exclude(&common)).sum();
+
+        Some(FilterReport {
+            limit,
+            discarded_hashes: common.len(),
+            removed_fingerprints,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arg::Lang;
+    use fuscum::fingerprint::{FingerPrintConfig, FingerPrintGenerator, WithFingerprint};
+    use fuscum::kgram::default_rolling_kgram;
+    use fuscum::preprocess::RegexPreprocessor;
+
+    fn submission(name: &str, sources: &[&str]) -> Submission {
+        let gen = FingerPrintGenerator {
+            config: FingerPrintConfig::builder().k(5).window_size(4).build(),
+            preprocessor: RegexPreprocessor::whitespace(),
+            kgram: Box::new(default_rolling_kgram()),
+        };
+        let files = sources
+            .iter()
+            .enumerate()
+            .map(|(i, src)| SourceFile {
+                name: format!("{name}/{i}"),
+                path: format!("{name}/{i}").into(),
+                lang: Lang::Python,
+                fingerprint: gen.generate(src),
+                functions: Vec::new(),
+            })
+            .collect();
+        Submission::new(name.to_string(), files)
+    }
+
+    #[test]
+    fn inverted_index_matches_pairwise_scores() {
+        let submissions = vec![
+            submission("a", &["the quick brown fox jumps over the lazy dog"]),
+            submission("b", &["0123456789 abcdefghij", "the quick brown fox"]),
+            submission("c", &["jumps over the lazy dog", "klmnopqrstuvwxyz"]),
+            submission("d", &["nothing in common with anyone else here"]),
+            submission("e", &["0123456789 abcdefghij klmnopqrstuvwxyz"]),
+        ];
+
+        for metric in [Metric::Containment, Metric::Jaccard, Metric::Dice] {
+            let analyzer = SimilarityAnalyzer::new(0.0, submissions.len(), metric, true);
+            let summaries = analyzer.analyze_fingerprints(&submissions).unwrap();
+            assert_eq!(summaries.len(), submissions.len());
+
+            for base in &submissions {
+                // every other submission by descending score, ties in their original order
+                let mut expected: Vec<(&str, f32)> = submissions
+                    .iter()
+                    .filter(|other| other.name != base.name)
+                    .map(|other| (other.name.as_str(), base.similarity_with(other, metric)))
+                    .collect();
+                expected.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+                let summary = summaries.iter().find(|s| s.base == base.name).unwrap();
+                let actual: Vec<(&str, f32)> = summary
+                    .against
+                    .iter()
+                    .map(|p| (p.against.as_str(), p.score))
+                    .collect();
+                assert_eq!(actual, expected, "{} with {:?}", base.name, metric);
+                assert_eq!(summary.max_score, expected[0].1);
+            }
+        }
+    }
+
+    #[test]
+    fn top_k_padding() {
+        let submissions = vec![
+            submission("a", &["abcdefghijklmnop"]),
+            submission("b", &["0123456789"]),
+            submission("c", &["qrstuvwxyz"]),
+            submission("d", &["abcdefghij"]),
+        ];
+        let analyzer = SimilarityAnalyzer::new(0.0, 2, Metric::Containment, true);
+        let summaries = analyzer.analyze_fingerprints(&submissions).unwrap();
+
+        let against = |name: &str| -> Vec<String> {
+            let summary = summaries.iter().find(|s| s.base == name).unwrap();
+            summary.against.iter().map(|p| p.against.clone()).collect()
+        };
+        // the only match comes first, then the first submission sharing nothing
+        assert_eq!(against("a"), ["d", "b"]);
+        // nothing shared at all: the first other submissions in order
+        assert_eq!(against("b"), ["a", "c"]);
+        assert_eq!(against("c"), ["a", "b"]);
+    }
+
+    #[test]
+    fn frequency_limit() {
+        let limit = |max_submissions, max_fraction, total| {
+            FrequencyFilter::new(max_submissions, max_fraction).limit(total)
+        };
+        assert_eq!(limit(None, None, 10), None);
+        assert_eq!(limit(Some(3), None, 10), Some(3));
+        // the fraction of the submissions is rounded down
+        assert_eq!(limit(None, Some(0.5), 5), Some(2));
+        assert_eq!(limit(None, Some(0.25), 3), Some(0));
+        assert_eq!(limit(None, Some(1.0), 7), Some(7));
+        // the stricter of both limits applies
+        assert_eq!(limit(Some(3), Some(0.5), 10), Some(3));
+        assert_eq!(limit(Some(6), Some(0.5), 10), Some(5));
+    }
+
+    #[test]
+    fn frequency_filter() {
+        let everyone = "import everything from the usual boilerplate";
+        let pair = "a helper only two of them happened to write";
+        let mut submissions = vec![
+            submission("a", &[everyone, pair, "the first solution of the task"]),
+            submission("b", &[everyone, pair, "0123456789 abcdefghij"]),
+            submission("c", &[everyone, "klmnopqrstuvwxyz !?"]),
+            submission("d", &["nothing in common with anyone else"]),
+        ];
+        let frequencies = |submissions: &[Submission]| {
+            let mut frequency: HashMap<u64, usize> = HashMap::new();
+            for s in submissions {
+                for &hash in s.hashes().iter() {
+                    *frequency.entry(hash).or_default() += 1;
+                }
+            }
+            frequency
+        };
+        let before = frequencies(&submissions);
+        assert_eq!(before.values().max(), Some(&3));
+
+        assert!(FrequencyFilter::new(None, None)
+            .apply(&mut submissions)
+            .is_none());
+        assert_eq!(frequencies(&submissions), before);
+
+        // hashes in more than 2 submissions are dropped, those in exactly 2 kept
+        let report = FrequencyFilter::new(None, Some(0.5))
+            .apply(&mut submissions)
+            .unwrap();
+        assert_eq!(report.limit, 2);
+        let after = frequencies(&submissions);
+        let common = before.values().filter(|&&count| count > 2).count();
+        assert!(common > 0);
+        assert_eq!(report.discarded_hashes, common);
+        assert!(report.removed_fingerprints >= 3 * common);
+        assert_eq!(after.values().max(), Some(&2));
+        assert_eq!(after.len(), before.len() - common);
+        assert!(!submissions[2].hashes().is_empty());
     }
 }
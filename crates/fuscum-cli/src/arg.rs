@@ -1,14 +1,22 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use clap::{Parser, Subcommand};
+use clap::builder::RangedU64ValueParser;
+use clap::parser::ValueSource;
+use clap::{ArgAction, ArgMatches, Parser, Subcommand};
 use clap_complete::shells::Shell;
 use fuscum::preprocess::{
-    CPreprocessor, CppPreprocessor, GoPreprocessor, JavaPreprocessor, JavaScriptPreprocessor,
-    PythonPreprocessor, RubyPreprocessor, RustPreprocessor, TypeScriptPreprocessor,
+    BashPreprocessor, CPreprocessor, CSharpPreprocessor, CppPreprocessor, GoPreprocessor,
+    HaskellPreprocessor, JavaPreprocessor, JavaScriptPreprocessor, KotlinPreprocessor,
+    LuaPreprocessor, PhpPreprocessor, PythonPreprocessor, RubyPreprocessor, RustPreprocessor,
+    ScalaPreprocessor, SwiftPreprocessor, TypeScriptPreprocessor,
 };
+use serde::{Deserialize, Serialize};
 
-#[derive(clap::ValueEnum, Clone, Debug)]
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum Lang {
+    /// Detect the language of every file from its extension or shebang line
+    Auto,
     Python,
     C,
     Cpp,
@@ -18,65 +26,402 @@ pub enum Lang {
     Go,
     Rust,
     Ruby,
+    CSharp,
+    Kotlin,
+    Swift,
+    Php,
+    Scala,
+    Haskell,
+    Lua,
+    Bash,
 }
 
-#[derive(clap::ValueEnum, Clone, Debug, Default)]
+#[derive(clap::ValueEnum, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum Hash {
     #[default]
     Rolling,
     Std,
 }
 
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Unit {
+    /// k-grams of bytes of the normalized source
+    #[default]
+    Bytes,
+    /// k-grams of tokens, i.e. the leaves of the syntax tree
+    Tokens,
+    /// Shapes of syntax subtrees of at least `--kgram-size` nodes, which
+    /// survive reordered statements and swapped branches
+    Structure,
+}
+
+impl From<Unit> for fuscum::fingerprint::Unit {
+    fn from(unit: Unit) -> Self {
+        match unit {
+            Unit::Bytes => Self::Bytes,
+            Unit::Tokens => Self::Tokens,
+            Unit::Structure => Self::Structure,
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Metric {
+    /// Shared fingerprints over those of the base file
+    #[default]
+    Containment,
+    /// Shared fingerprints over the union of both files
+    Jaccard,
+    /// Twice the shared fingerprints over the sum of both files
+    Dice,
+    /// Shared fingerprints over those of the smaller file
+    Overlap,
+    /// The lower of both containment scores
+    MinContainment,
+    /// The higher of both containment scores
+    MaxContainment,
+}
+
+impl From<Metric> for fuscum::fingerprint::Metric {
+    fn from(metric: Metric) -> Self {
+        match metric {
+            Metric::Containment => Self::Containment,
+            Metric::Jaccard => Self::Jaccard,
+            Metric::Dice => Self::Dice,
+            Metric::Overlap => Self::Overlap,
+            Metric::MinContainment => Self::MinContainment,
+            Metric::MaxContainment => Self::MaxContainment,
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CrossLang {
+    /// Compare submissions regardless of their language
+    #[default]
+    Include,
+    /// Only compare submissions of the same language
+    Exclude,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default)]
+pub enum GroupBy {
+    /// Every file is a submission of its own
+    #[default]
+    File,
+    /// Files under the same directory form one submission
+    Dir,
+}
+
+/// Build a language preprocessor with the given options
+macro_rules! preprocessor {
+    ($preprocessor:ty, $options:expr) => {
+        Box::new(
+            <$preprocessor>::builder()
+                .subst_var_opt($options.subst_var())
+                .subst_string_opt($options.subst_string())
+                .subst_literal_opt($options.subst_literal())
+                .remove_comments(!$options.keep_comments)
+                .build(),
+        )
+    };
+}
+
 impl Lang {
-    pub fn preprocessor(&self) -> Box<dyn fuscum::preprocess::Preprocessor> {
+    pub fn preprocessor(
+        &self,
+        options: &PreprocessArgs,
+    ) -> Box<dyn fuscum::preprocess::Preprocessor> {
         match self {
-            Lang::Python => Box::new(PythonPreprocessor::default()),
-            Lang::C => Box::new(CPreprocessor::default()),
-            Lang::Cpp => Box::new(CppPreprocessor::default()),
-            Lang::JavaScript => Box::new(JavaScriptPreprocessor::default()),
-            Lang::TypeScript => Box::new(TypeScriptPreprocessor::default()),
-            Lang::Java => Box::new(JavaPreprocessor::default()),
-            Lang::Go => Box::new(GoPreprocessor::default()),
-            Lang::Rust => Box::new(RustPreprocessor::default()),
-            Lang::Ruby => Box::new(RubyPreprocessor::default()),
+            Lang::Python => preprocessor!(PythonPreprocessor, options),
+            Lang::C => preprocessor!(CPreprocessor, options),
+            Lang::Cpp => preprocessor!(CppPreprocessor, options),
+            Lang::JavaScript => preprocessor!(JavaScriptPreprocessor, options),
+            Lang::TypeScript => preprocessor!(TypeScriptPreprocessor, options),
+            Lang::Java => preprocessor!(JavaPreprocessor, options),
+            Lang::Go => preprocessor!(GoPreprocessor, options),
+            Lang::Rust => preprocessor!(RustPreprocessor, options),
+            Lang::Ruby => preprocessor!(RubyPreprocessor, options),
+            Lang::CSharp => preprocessor!(CSharpPreprocessor, options),
+            Lang::Kotlin => preprocessor!(KotlinPreprocessor, options),
+            Lang::Swift => preprocessor!(SwiftPreprocessor, options),
+            Lang::Php => preprocessor!(PhpPreprocessor, options),
+            Lang::Scala => preprocessor!(ScalaPreprocessor, options),
+            Lang::Haskell => preprocessor!(HaskellPreprocessor, options),
+            Lang::Lua => preprocessor!(LuaPreprocessor, options),
+            Lang::Bash => preprocessor!(BashPreprocessor, options),
+            Lang::Auto => unreachable!("the language is detected for every file"),
+        }
+    }
+
+    /// Detect the language of a file from its extension, looked up in the
+    /// overrides first, or else from the interpreter of its shebang line
+    pub fn detect(path: &Path, src: &str, overrides: &[(String, Lang)]) -> Option<Lang> {
+        if let Some(ext) = path.extension().and_then(|ext| ext.to_str()) {
+            if let Some((_, lang)) = overrides.iter().find(|(e, _)| e.eq_ignore_ascii_case(ext)) {
+                return Some(*lang);
+            }
+            if let Some(lang) = Self::from_extension(ext) {
+                return Some(lang);
+            }
         }
+        Self::from_shebang(src)
+    }
+
+    fn from_extension(ext: &str) -> Option<Lang> {
+        let lang = match ext.to_ascii_lowercase().as_str() {
+            "py" | "pyw" => Lang::Python,
+            "c" | "h" => Lang::C,
+            "cc" | "cpp" | "cxx" | "c++" | "hh" | "hpp" | "hxx" => Lang::Cpp,
+            "js" | "mjs" | "cjs" | "jsx" => Lang::JavaScript,
+            "ts" | "mts" | "cts" | "tsx" => Lang::TypeScript,
+            "java" => Lang::Java,
+            "go" => Lang::Go,
+            "rs" => Lang::Rust,
+            "rb" => Lang::Ruby,
+            "cs" => Lang::CSharp,
+            "kt" | "kts" => Lang::Kotlin,
+            "swift" => Lang::Swift,
+            "php" => Lang::Php,
+            "scala" | "sc" => Lang::Scala,
+            "hs" | "lhs" => Lang::Haskell,
+            "lua" => Lang::Lua,
+            "sh" | "bash" => Lang::Bash,
+            _ => return None,
+        };
+        Some(lang)
+    }
+
+    fn from_shebang(src: &str) -> Option<Lang> {
+        let line = src.lines().next()?.strip_prefix("#!")?;
+        // skip `env` and its flags in `#!/usr/bin/env -S python3 -u`
+        let interpreter = line
+            .split_whitespace()
+            .map(|word| word.rsplit('/').next().unwrap_or(word))
+            .find(|word| *word != "env" && !word.starts_with('-'))?;
+        let lang = match interpreter.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.') {
+            "python" => Lang::Python,
+            "node" | "nodejs" => Lang::JavaScript,
+            "deno" | "ts-node" => Lang::TypeScript,
+            "ruby" => Lang::Ruby,
+            "php" => Lang::Php,
+            "lua" | "luajit" => Lang::Lua,
+            "sh" | "bash" => Lang::Bash,
+            "runghc" | "runhaskell" => Lang::Haskell,
+            "kotlin" => Lang::Kotlin,
+            "scala" => Lang::Scala,
+            "swift" => Lang::Swift,
+            _ => return None,
+        };
+        Some(lang)
     }
 }
 
-#[derive(Parser, Clone, Debug)]
-pub struct Args {
+/// Parse an `EXT=LANG` mapping of a file extension to a language
+fn parse_lang_mapping(s: &str) -> Result<(String, Lang), String> {
+    let (ext, lang) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected EXT=LANG, got {s}"))?;
+    let lang = <Lang as clap::ValueEnum>::from_str(lang, true)?;
+    if lang == Lang::Auto {
+        return Err("an extension cannot be mapped to auto".to_string());
+    }
+    Ok((ext.trim_start_matches('.').to_string(), lang))
+}
+
+/// Which parts of the source are replaced with placeholders
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Normalize {
+    /// Identifiers and literals
+    #[default]
+    All,
+    /// Identifiers only, keeping literals as written
+    Identifiers,
+    /// Literals only, keeping identifiers as written
+    Literals,
+    /// Nothing, the source is only stripped of whitespace (and comments)
+    None,
+}
+
+/// How the language preprocessors normalize the source
+#[derive(clap::Args, Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct PreprocessArgs {
+    /// Which parts of the source are replaced with placeholders
+    #[arg(long, value_enum, default_value = "all")]
+    pub normalize: Normalize,
+
+    /// Keep comments instead of removing them
+    #[arg(long, action = ArgAction::Set, num_args = 0..=1, require_equals = true, default_value = "false", default_missing_value = "true")]
+    pub keep_comments: bool,
+
+    /// Also replace numeric, char, boolean and null literals with placeholders
+    #[arg(long, action = ArgAction::Set, num_args = 0..=1, require_equals = true, default_value = "false", default_missing_value = "true")]
+    pub normalize_constants: bool,
+}
+
+impl PreprocessArgs {
+    fn subst_var(&self) -> Option<String> {
+        matches!(self.normalize, Normalize::All | Normalize::Identifiers).then(|| "v".to_string())
+    }
+
+    fn subst_string(&self) -> Option<String> {
+        matches!(self.normalize, Normalize::All | Normalize::Literals).then(|| "\"s\"".to_string())
+    }
+
+    fn subst_literal(&self) -> Option<String> {
+        self.normalize_constants.then(|| "0".to_string())
+    }
+}
+
+/// Parse a fraction between 0 and 1
+fn parse_fraction(s: &str) -> Result<f32, String> {
+    let value: f32 = s.parse().map_err(|e| format!("{e}"))?;
+    if (0.0..=1.0).contains(&value) {
+        Ok(value)
+    } else {
+        Err(format!("{value} is not between 0 and 1"))
+    }
+}
+
+/// Where to find the submissions and how their files are grouped
+#[derive(clap::Args, Clone, Debug)]
+pub struct InputArgs {
     /// The directory to search for source files
     #[arg(default_value = ".")]
     pub dir: PathBuf,
 
     /// The glob pattern to match source files within the directory
+    #[arg(long, required = true)]
+    pub pat: Option<String>,
+
+    /// How files are grouped into submissions
+    #[arg(long, value_enum, default_value = "file")]
+    pub group_by: GroupBy,
+
+    /// Number of leading directories naming a submission when grouping by directory
+    #[arg(long, default_value = "1", value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    pub group_depth: usize,
+
+    /// Regex over the relative file path naming its submission (the first capture
+    /// group, or the whole match); takes precedence over `--group-by`
+    #[arg(long)]
+    pub group_regex: Option<String>,
+
+    /// Decode files which are not valid UTF-8 as Windows-1252 (a superset of
+    /// Latin-1) instead of skipping them
     #[arg(long)]
-    pub pat: String,
+    pub lossy: bool,
+}
+
+impl InputArgs {
+    /// The files matching `pat` within `dir`, each as its own submission;
+    /// without a pattern, the files with an extension of the language
+    pub fn new(dir: PathBuf, pat: Option<String>, lossy: bool) -> Self {
+        Self {
+            dir,
+            pat,
+            group_by: GroupBy::File,
+            group_depth: 1,
+            group_regex: None,
+            lossy,
+        }
+    }
+}
 
+/// How source files are fingerprinted
+#[derive(clap::Args, Clone, Debug, Serialize, Deserialize)]
+pub struct FingerprintArgs {
     /// The language of the source files
     #[arg(long)]
     pub lang: Lang,
 
-    /// The threshold to consider two files similar
-    #[arg(long, default_value = "0.4")]
-    pub threshold: f32,
-
-    /// Number of top matches to preserve per file
-    #[arg(long, default_value = "3")]
-    pub top_k: usize,
+    /// Detect files with the given extension as the given language with
+    /// `--lang auto`, e.g. `h=cpp`; repeatable
+    #[arg(long, value_name = "EXT=LANG", value_parser = parse_lang_mapping)]
+    #[serde(default)]
+    pub lang_map: Vec<(String, Lang)>,
 
     /// Size of k-grams for fingerprinting
-    #[arg(long, default_value = "35")]
+    #[arg(long, default_value = "35", value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
     pub kgram_size: usize,
 
     /// Window size for winnowing
-    #[arg(long, default_value = "40")]
+    #[arg(long, default_value = "40", value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
     pub window_size: usize,
 
     /// Hash algorithm to use for k-gram fingerprinting
     #[arg(long, value_enum, default_value = "rolling")]
     pub hash: Hash,
 
+    /// Use robust winnowing, which selects fewer fingerprints from long
+    /// low-entropy sequences such as repeated statements
+    #[arg(long, action = ArgAction::Set, num_args = 0..=1, require_equals = true, default_value = "false", default_missing_value = "true")]
+    #[serde(default)]
+    pub robust: bool,
+
+    /// What k-grams are formed over; with tokens, `--kgram-size` counts
+    /// tokens rather than bytes, so it should be much smaller, and with
+    /// structure it is the minimum number of nodes of a subtree
+    #[arg(long, value_enum, default_value = "bytes")]
+    #[serde(default)]
+    pub unit: Unit,
+
+    /// Also fingerprint every function and method on its own, and report
+    /// the best matching functions across submissions
+    #[arg(long, action = ArgAction::Set, num_args = 0..=1, require_equals = true, default_value = "false", default_missing_value = "true")]
+    #[serde(default)]
+    pub functions: bool,
+
+    #[command(flatten)]
+    #[serde(default)]
+    pub preprocess: PreprocessArgs,
+}
+
+impl FingerprintArgs {
+    /// The options given on the command line whose values differ from the
+    /// given settings, leaving out those that were defaulted
+    pub fn conflicts(&self, settings: &Self, matches: &ArgMatches) -> Vec<String> {
+        let preprocess = &self.preprocess;
+        [
+            ("lang", self.lang != settings.lang),
+            ("lang_map", self.lang_map != settings.lang_map),
+            ("kgram_size", self.kgram_size != settings.kgram_size),
+            ("window_size", self.window_size != settings.window_size),
+            ("hash", self.hash != settings.hash),
+            ("robust", self.robust != settings.robust),
+            ("unit", self.unit != settings.unit),
+            ("functions", self.functions != settings.functions),
+            (
+                "normalize",
+                preprocess.normalize != settings.preprocess.normalize,
+            ),
+            (
+                "keep_comments",
+                preprocess.keep_comments != settings.preprocess.keep_comments,
+            ),
+            (
+                "normalize_constants",
+                preprocess.normalize_constants != settings.preprocess.normalize_constants,
+            ),
+        ]
+        .into_iter()
+        .filter(|&(id, differs)| {
+            differs && matches.value_source(id) == Some(ValueSource::CommandLine)
+        })
+        .map(|(id, _)| format!("--{}", id.replace('_', "-")))
+        .collect()
+    }
+}
+
+/// Where to write the results, besides the console table
+#[derive(clap::Args, Clone, Debug)]
+pub struct OutputArgs {
     /// Write JSON results to this file
     #[arg(long)]
     pub json: Option<PathBuf>,
@@ -84,11 +429,92 @@ pub struct Args {
     /// Write network visualization HTML to this file
     #[arg(long)]
     pub network: Option<PathBuf>,
+
+    /// Write a side-by-side HTML report of suspicious pairs to this directory
+    #[arg(long)]
+    pub report: Option<PathBuf>,
+
+    /// Write the files skipped during the scan, with the reason why, to this JSON file
+    #[arg(long)]
+    pub errors: Option<PathBuf>,
+
+    /// Write the best matching functions (see `--functions`) to this JSON file
+    #[arg(long)]
+    pub functions_json: Option<PathBuf>,
+}
+
+/// How pairs of submissions are scored and reported
+#[derive(clap::Args, Clone, Debug)]
+pub struct AnalysisArgs {
+    /// The threshold to consider two files similar
+    #[arg(long, default_value = "0.4", value_parser = parse_fraction)]
+    pub threshold: f32,
+
+    /// Number of top matches to preserve per file
+    #[arg(long, default_value = "3")]
+    pub top_k: usize,
+
+    /// Similarity metric to score pairs of files with
+    #[arg(long, value_enum, default_value = "containment")]
+    pub metric: Metric,
+
+    /// Whether submissions detected as different languages are compared
+    #[arg(long, value_enum, default_value = "include")]
+    pub cross_lang: CrossLang,
+}
+
+/// Which hashes are too common across the submissions to be evidence of copying
+#[derive(clap::Args, Clone, Debug)]
+pub struct FilterArgs {
+    /// Ignore hashes appearing in more than this many submissions
+    #[arg(long)]
+    pub max_submissions: Option<usize>,
+
+    /// Ignore hashes appearing in more than this fraction of the submissions
+    #[arg(long, value_parser = parse_fraction)]
+    pub max_fraction: Option<f32>,
+}
+
+#[derive(Parser, Clone, Debug)]
+pub struct Args {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    #[command(flatten)]
+    pub fingerprint: FingerprintArgs,
+
+    #[command(flatten)]
+    pub analysis: AnalysisArgs,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+
+    /// Base code (file, directory or glob) whose fingerprints are excluded
+    /// from every submission, e.g. the starter code of an assignment
+    #[arg(long)]
+    pub base: Vec<String>,
+
+    /// Directory of archived submissions (e.g. from past terms), searched with
+    /// `--pat`, which are compared against the current ones but never among themselves
+    #[arg(long)]
+    pub archive: Vec<PathBuf>,
+
+    /// Write JSON results of the matches against the archive to this file
+    #[arg(long)]
+    pub archive_json: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub filter: FilterArgs,
 }
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 pub struct Cli {
+    /// Read default settings from this TOML file; options given on the
+    /// command line take precedence over it
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+
     #[command(subcommand)]
     pub command: Commands,
 }
@@ -96,7 +522,97 @@ pub struct Cli {
 #[derive(Subcommand)]
 pub enum Commands {
     /// Run similarity detection
-    Scan(Args),
+    Scan(Box<Args>),
+    /// Build or query a persistent fingerprint index
+    #[command(subcommand)]
+    Index(Box<IndexCommands>),
+    /// Write the given settings to a TOML config file, for use with `--config`
+    Config {
+        #[command(flatten)]
+        fingerprint: FingerprintArgs,
+
+        #[command(flatten)]
+        analysis: AnalysisArgs,
+
+        #[command(flatten)]
+        filter: FilterArgs,
+
+        /// The config file to write, instead of the standard output
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Rank a corpus by similarity to a single file or directory, without
+    /// comparing the corpus against itself
+    #[command(
+        mut_arg("dir", |arg| arg
+            .long("against")
+            .value_name("AGAINST")
+            .required(true)
+            .default_value(None)
+            .help("The corpus: a directory of submissions, or an index file built with \
+                `index build`, whose fingerprint settings are then used instead")),
+        mut_arg("pat", |arg| arg
+            .required(false)
+            .help("The glob pattern to match source files within directories; by \
+                default, the files with an extension of `--lang`, or of any language \
+                with `--lang auto`")),
+        mut_arg("lang", |arg| arg.required(false).default_value("auto")),
+    )]
+    Query {
+        /// The file or directory to query
+        file: PathBuf,
+
+        #[command(flatten)]
+        corpus: InputArgs,
+
+        #[command(flatten)]
+        fingerprint: FingerprintArgs,
+
+        #[command(flatten)]
+        analysis: AnalysisArgs,
+
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Compare two files or directories in detail, each directory being one submission
+    Compare {
+        /// The first file or directory
+        a: PathBuf,
+
+        /// The second file or directory
+        b: PathBuf,
+
+        /// The glob pattern to match source files within directories; by
+        /// default, the files with an extension of `--lang`, or of any
+        /// language with `--lang auto`
+        #[arg(long)]
+        pat: Option<String>,
+
+        /// Decode files which are not valid UTF-8 as Windows-1252 instead of skipping them
+        #[arg(long)]
+        lossy: bool,
+
+        #[command(flatten)]
+        fingerprint: FingerprintArgs,
+
+        /// Write a side-by-side HTML report of the matched regions to this directory
+        #[arg(long)]
+        report: Option<PathBuf>,
+    },
+    /// Show what a file is reduced to before fingerprinting: its normalized
+    /// text, or its tokens or subtrees with `--unit`, k-gram count, and the
+    /// fingerprints selected with the lines they cover
+    Inspect {
+        /// The file to inspect
+        file: PathBuf,
+
+        #[command(flatten)]
+        fingerprint: FingerprintArgs,
+
+        /// Decode the file as Windows-1252 if it is not valid UTF-8
+        #[arg(long)]
+        lossy: bool,
+    },
     /// Generate shell completions
     Completions {
         /// The shell to generate completions for
@@ -104,3 +620,170 @@ pub enum Commands {
         shell: Shell,
     },
 }
+
+#[derive(Subcommand)]
+pub enum IndexCommands {
+    /// Fingerprint submissions once and store them in an index file
+    Build {
+        #[command(flatten)]
+        input: InputArgs,
+
+        #[command(flatten)]
+        fingerprint: FingerprintArgs,
+
+        /// The index file to write
+        #[arg(long, short)]
+        output: PathBuf,
+    },
+    /// Compare new submissions against the submissions of an index
+    Query {
+        /// The index file to compare against
+        #[arg(long)]
+        index: PathBuf,
+
+        #[command(flatten)]
+        input: InputArgs,
+
+        #[command(flatten)]
+        analysis: AnalysisArgs,
+
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_by_extension() {
+        let detect = |path: &str| Lang::detect(Path::new(path), "", &[]);
+        assert_eq!(detect("a/main.py"), Some(Lang::Python));
+        assert_eq!(detect("Main.JAVA"), Some(Lang::Java));
+        assert_eq!(detect("lib.Rs"), Some(Lang::Rust));
+        assert_eq!(detect("util.h"), Some(Lang::C));
+        assert_eq!(detect("notes.txt"), None);
+        assert_eq!(detect("Makefile"), None);
+    }
+
+    #[test]
+    fn detect_by_shebang() {
+        let shebang = |line: &str| Lang::from_shebang(&format!("{line}\nprint(1)\n"));
+        assert_eq!(shebang("#!/usr/bin/python3"), Some(Lang::Python));
+        assert_eq!(shebang("#!/usr/bin/python3.11"), Some(Lang::Python));
+        assert_eq!(shebang("#!/usr/bin/env python3.11"), Some(Lang::Python));
+        assert_eq!(shebang("#!/usr/bin/env -S python3 -u"), Some(Lang::Python));
+        assert_eq!(shebang("#! /bin/bash -e"), Some(Lang::Bash));
+        assert_eq!(shebang("#!/usr/bin/env node"), Some(Lang::JavaScript));
+        assert_eq!(shebang("#!/usr/bin/env perl"), None);
+        assert_eq!(Lang::from_shebang("print(1)\n#!/usr/bin/python3"), None);
+        assert_eq!(Lang::from_shebang(""), None);
+
+        // only consulted without a known extension
+        let src = "#!/usr/bin/env ruby\n";
+        assert_eq!(
+            Lang::detect(Path::new("script"), src, &[]),
+            Some(Lang::Ruby)
+        );
+        assert_eq!(
+            Lang::detect(Path::new("a.py"), src, &[]),
+            Some(Lang::Python)
+        );
+    }
+
+    #[test]
+    fn lang_map_takes_precedence() {
+        let overrides = [
+            parse_lang_mapping("h=cpp").unwrap(),
+            parse_lang_mapping(".inc=php").unwrap(),
+        ];
+        let detect = |path: &str, src: &str| Lang::detect(Path::new(path), src, &overrides);
+        assert_eq!(detect("util.h", ""), Some(Lang::Cpp));
+        assert_eq!(detect("UTIL.H", ""), Some(Lang::Cpp));
+        assert_eq!(detect("header.inc", "#!/bin/sh\n"), Some(Lang::Php));
+        assert_eq!(detect("main.c", ""), Some(Lang::C));
+
+        assert!(parse_lang_mapping("h").is_err());
+        assert!(parse_lang_mapping("h=auto").is_err());
+        assert!(parse_lang_mapping("h=cobol").is_err());
+    }
+
+    #[test]
+    fn query_corpus() {
+        use clap::CommandFactory;
+
+        Cli::command().debug_assert();
+        let cli = Cli::try_parse_from([
+            "fuscum-cli",
+            "query",
+            "late.py",
+            "--against",
+            "2025",
+            "--group-regex",
+            "^([^/]+)/",
+        ])
+        .unwrap();
+        let Commands::Query {
+            file,
+            corpus,
+            fingerprint,
+            ..
+        } = cli.command
+        else {
+            unreachable!()
+        };
+        assert_eq!(file, Path::new("late.py"));
+        assert_eq!(corpus.dir, Path::new("2025"));
+        assert_eq!(corpus.group_regex.as_deref(), Some("^([^/]+)/"));
+        assert_eq!(corpus.pat, None);
+        assert_eq!(fingerprint.lang, Lang::Auto);
+
+        // the corpus is not positional
+        assert!(Cli::try_parse_from(["fuscum-cli", "query", "late.py", "2025"]).is_err());
+    }
+
+    #[test]
+    fn conflicting_settings() {
+        use clap::{CommandFactory, FromArgMatches};
+
+        let parse = |args: &[&str]| {
+            let matches = Cli::command()
+                .try_get_matches_from(
+                    ["fuscum-cli", "query", "late.py", "--against", "index.json"]
+                        .iter()
+                        .chain(args),
+                )
+                .unwrap();
+            let Commands::Query { fingerprint, .. } =
+                Cli::from_arg_matches(&matches).unwrap().command
+            else {
+                unreachable!()
+            };
+            (
+                fingerprint,
+                matches.subcommand_matches("query").unwrap().clone(),
+            )
+        };
+        let (settings, _) = parse(&["--lang", "python", "--kgram-size", "30", "--robust"]);
+
+        // defaulted options are left to the settings of the index
+        let (fingerprint, matches) = parse(&[]);
+        assert!(fingerprint.conflicts(&settings, &matches).is_empty());
+
+        // as are options given with the same values
+        let (fingerprint, matches) = parse(&["--lang", "python", "--robust=true"]);
+        assert!(fingerprint.conflicts(&settings, &matches).is_empty());
+
+        let (fingerprint, matches) = parse(&["--kgram-size", "20", "--unit", "tokens"]);
+        assert_eq!(
+            fingerprint.conflicts(&settings, &matches),
+            ["--kgram-size", "--unit"]
+        );
+        let (fingerprint, matches) = parse(&["--lang", "java", "--normalize-constants"]);
+        assert_eq!(
+            fingerprint.conflicts(&settings, &matches),
+            ["--lang", "--normalize-constants"]
+        );
+    }
+}
@@ -0,0 +1,248 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use clap::{Command, ValueEnum};
+use serde::{Deserialize, Serialize};
+
+use crate::arg::{
+    AnalysisArgs, CrossLang, FilterArgs, FingerprintArgs, Hash, Lang, Metric, Normalize, Unit,
+};
+
+/// Settings read from a TOML file, e.g. checked into the repository of a course
+/// so that every scan is reproducible.
+///
+/// Every setting is the default of the command line option of the same name,
+/// so that options given explicitly still take precedence.
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    pub fingerprint: FingerprintConfig,
+    pub preprocess: PreprocessConfig,
+    pub analysis: AnalysisConfig,
+    pub filter: FilterConfig,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct FingerprintConfig {
+    pub lang: Option<Lang>,
+    /// Languages of file extensions, like `--lang-map EXT=LANG`
+    pub lang_map: Option<BTreeMap<String, Lang>>,
+    pub kgram_size: Option<usize>,
+    pub window_size: Option<usize>,
+    pub hash: Option<Hash>,
+    pub robust: Option<bool>,
+    pub unit: Option<Unit>,
+    pub functions: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct PreprocessConfig {
+    pub normalize: Option<Normalize>,
+    pub keep_comments: Option<bool>,
+    pub normalize_constants: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct AnalysisConfig {
+    pub threshold: Option<f64>,
+    pub top_k: Option<usize>,
+    pub metric: Option<Metric>,
+    pub cross_lang: Option<CrossLang>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct FilterConfig {
+    pub max_submissions: Option<usize>,
+    pub max_fraction: Option<f64>,
+}
+
+impl Config {
+    /// Capture the settings of a run
+    pub fn new(
+        fingerprint: &FingerprintArgs,
+        analysis: &AnalysisArgs,
+        filter: &FilterArgs,
+    ) -> Self {
+        Self {
+            fingerprint: FingerprintConfig {
+                lang: Some(fingerprint.lang),
+                lang_map: (!fingerprint.lang_map.is_empty())
+                    .then(|| fingerprint.lang_map.iter().cloned().collect()),
+                kgram_size: Some(fingerprint.kgram_size),
+                window_size: Some(fingerprint.window_size),
+                hash: Some(fingerprint.hash.clone()),
+                robust: Some(fingerprint.robust),
+                unit: Some(fingerprint.unit),
+                functions: Some(fingerprint.functions),
+            },
+            preprocess: PreprocessConfig {
+                normalize: Some(fingerprint.preprocess.normalize),
+                keep_comments: Some(fingerprint.preprocess.keep_comments),
+                normalize_constants: Some(fingerprint.preprocess.normalize_constants),
+            },
+            analysis: AnalysisConfig {
+                // go through the shortest representation, so that 0.4 is not written as 0.4000000059604645
+                threshold: analysis.threshold.to_string().parse().ok(),
+                top_k: Some(analysis.top_k),
+                metric: Some(analysis.metric),
+                cross_lang: Some(analysis.cross_lang),
+            },
+            filter: FilterConfig {
+                max_submissions: filter.max_submissions,
+                max_fraction: filter.max_fraction.and_then(|v| v.to_string().parse().ok()),
+            },
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        toml::from_str(&content).with_context(|| format!("invalid config {}", path.display()))
+    }
+
+    pub fn to_toml(&self) -> Result<String> {
+        toml::to_string_pretty(self).context("failed to serialize config")
+    }
+
+    /// The default values of the command line options, keyed by argument id
+    fn defaults(&self) -> Vec<(&'static str, Vec<String>)> {
+        let fingerprint = &self.fingerprint;
+        let preprocess = &self.preprocess;
+        let analysis = &self.analysis;
+        let filter = &self.filter;
+        let mut defaults: Vec<(&'static str, Vec<String>)> = [
+            ("lang", fingerprint.lang.as_ref().map(value_name)),
+            ("kgram_size", fingerprint.kgram_size.map(|v| v.to_string())),
+            (
+                "window_size",
+                fingerprint.window_size.map(|v| v.to_string()),
+            ),
+            ("hash", fingerprint.hash.as_ref().map(value_name)),
+            ("robust", fingerprint.robust.map(|v| v.to_string())),
+            ("unit", fingerprint.unit.as_ref().map(value_name)),
+            ("functions", fingerprint.functions.map(|v| v.to_string())),
+            ("normalize", preprocess.normalize.as_ref().map(value_name)),
+            (
+                "keep_comments",
+                preprocess.keep_comments.map(|v| v.to_string()),
+            ),
+            (
+                "normalize_constants",
+                preprocess.normalize_constants.map(|v| v.to_string()),
+            ),
+            ("threshold", analysis.threshold.map(|v| v.to_string())),
+            ("top_k", analysis.top_k.map(|v| v.to_string())),
+            ("metric", analysis.metric.as_ref().map(value_name)),
+            ("cross_lang", analysis.cross_lang.as_ref().map(value_name)),
+            (
+                "max_submissions",
+                filter.max_submissions.map(|v| v.to_string()),
+            ),
+            ("max_fraction", filter.max_fraction.map(|v| v.to_string())),
+        ]
+        .into_iter()
+        .filter_map(|(id, value)| Some((id, vec![value?])))
+        .collect();
+        // a repeatable option, whose defaults are all the mappings
+        if let Some(lang_map) = &fingerprint.lang_map {
+            let mappings = lang_map
+                .iter()
+                .map(|(ext, lang)| format!("{ext}={}", value_name(lang)))
+                .collect();
+            defaults.push(("lang_map", mappings));
+        }
+        defaults
+    }
+
+    /// Use the settings as the defaults of the options of every subcommand,
+    /// so that they still go through the same validation
+    pub fn apply(&self, cmd: Command) -> Command {
+        apply_defaults(cmd, &self.defaults())
+    }
+}
+
+/// The name of a value on the command line
+pub fn value_name<T: ValueEnum>(value: &T) -> String {
+    value
+        .to_possible_value()
+        .expect("no skipped values")
+        .get_name()
+        .to_string()
+}
+
+fn apply_defaults(cmd: Command, defaults: &[(&'static str, Vec<String>)]) -> Command {
+    cmd.mut_args(
+        |arg| match defaults.iter().find(|(id, _)| arg.get_id().as_str() == *id) {
+            Some((_, values)) => arg.default_values(values.clone()).required(false),
+            None => arg,
+        },
+    )
+    .mut_subcommands(|sub| apply_defaults(sub, defaults))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use clap::{CommandFactory, Parser};
+
+    use super::*;
+    use crate::arg::{Cli, Commands};
+
+    #[test]
+    fn every_setting_is_a_default() {
+        let cli = Cli::try_parse_from([
+            "fuscum-cli",
+            "config",
+            "--lang",
+            "auto",
+            "--lang-map",
+            "h=cpp",
+            "--max-submissions",
+            "3",
+            "--max-fraction",
+            "0.5",
+        ])
+        .unwrap();
+        let Commands::Config {
+            fingerprint,
+            analysis,
+            filter,
+            ..
+        } = cli.command
+        else {
+            unreachable!()
+        };
+        let config = Config::new(&fingerprint, &analysis, &filter);
+
+        let table: toml::Table = toml::from_str(&config.to_toml().unwrap()).unwrap();
+        let keys: BTreeSet<String> = table
+            .values()
+            .flat_map(|section| section.as_table().unwrap().keys())
+            .map(|key| key.replace('-', "_"))
+            .collect();
+        let defaults: BTreeSet<String> = config
+            .defaults()
+            .into_iter()
+            .map(|(id, _)| id.to_string())
+            .collect();
+        assert_eq!(keys, defaults);
+
+        // and each of them is the id of an option
+        let cmd = Cli::command();
+        let ids: BTreeSet<&str> = cmd
+            .get_subcommands()
+            .flat_map(|sub| sub.get_arguments())
+            .map(|arg| arg.get_id().as_str())
+            .collect();
+        for id in &defaults {
+            assert!(ids.contains(id.as_str()), "no option {id}");
+        }
+    }
+}
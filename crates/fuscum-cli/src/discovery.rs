@@ -1,63 +1,488 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashSet};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Component, Path, PathBuf};
 
 use anyhow::{Context, Result};
-use fuscum::fingerprint::{FingerPrint, FingerPrintConfig, FingerPrintGenerator};
+use fuscum::fingerprint::{
+    FingerPrint, FingerPrintConfig, FingerPrintGenerator, FunctionFingerPrint, WithFingerprint,
+};
 use fuscum::kgram::{default_rolling_kgram, StdHashKgram};
+use fuscum::preprocess::Preprocessor;
 use rayon::prelude::*;
+use regex::Regex;
 
-use crate::arg::{Args, Hash};
+use serde::{Deserialize, Serialize};
 
-pub struct Submission {
+use crate::arg::{FingerprintArgs, GroupBy, Hash, InputArgs, Lang};
+
+/// A single fingerprinted file of a submission
+#[derive(Serialize, Deserialize)]
+pub struct SourceFile {
+    /// the path of the file, relative to the scanned directory where possible
     pub name: String,
+    pub path: PathBuf,
+    /// the language the file was fingerprinted as
+    pub lang: Lang,
     pub fingerprint: FingerPrint,
+    /// the functions of the file, if fingerprinted with `--functions`
+    #[serde(default)]
+    pub functions: Vec<FunctionFingerPrint>,
+}
+
+/// A submission made of one or more files, whose fingerprint is the union of its files
+#[derive(Serialize, Deserialize)]
+pub struct Submission {
+    pub name: String,
+    /// the language of all files, or none if they are of different languages
+    pub lang: Option<Lang>,
+    pub files: Vec<SourceFile>,
+}
+
+impl Submission {
+    pub fn new(name: String, files: Vec<SourceFile>) -> Self {
+        let lang = files.first().map(|f| f.lang);
+        let lang = lang.filter(|&lang| files.iter().all(|f| f.lang == lang));
+        Self { name, lang, files }
+    }
+
+    /// Whether two submissions are known to be of different languages
+    pub fn is_cross_lang(&self, other: &Submission) -> bool {
+        matches!((self.lang, other.lang), (Some(a), Some(b)) if a != b)
+    }
+
+    /// Drop the given hashes from every file and function,
+    /// returning the number of fingerprints removed from the files
+    pub fn exclude(&mut self, hashes: &HashSet<u64>) -> usize {
+        self.files
+            .iter_mut()
+            .map(|f| {
+                for function in &mut f.functions {
+                    function.fingerprint.exclude(hashes);
+                }
+                f.fingerprint.exclude(hashes)
+            })
+            .sum()
+    }
+
+    /// The distinct hashes of all files in ascending order
+    pub fn hashes(&self) -> Cow<'_, [u64]> {
+        match self.files.as_slice() {
+            [file] => Cow::Borrowed(file.fingerprint.hashes()),
+            files => {
+                let mut hashes: Vec<u64> = files
+                    .iter()
+                    .flat_map(|f| f.fingerprint.hashes())
+                    .copied()
+                    .collect();
+                hashes.sort_unstable();
+                hashes.dedup();
+                Cow::Owned(hashes)
+            }
+        }
+    }
+}
+
+/// A file left out of the scan, with the reason why
+#[derive(Debug, Serialize)]
+pub struct SkippedFile {
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+impl WithFingerprint for Submission {
+    type Hash = u64;
+
+    fn fingerprint(&self) -> HashSet<Self::Hash> {
+        self.hashes().iter().copied().collect()
+    }
 }
 
 pub struct FileDiscovery {
-    args: Args,
+    input: InputArgs,
+    fingerprint: FingerprintArgs,
 }
 
 impl FileDiscovery {
-    pub fn new(args: Args) -> Self {
-        Self { args }
+    pub fn new(input: InputArgs, fingerprint: FingerprintArgs) -> Self {
+        Self { input, fingerprint }
     }
 
-    pub fn discover_files(&self) -> Result<Vec<PathBuf>> {
-        let full_pat = self.args.dir.join(&self.args.pat);
-        let full_pat = full_pat.to_string_lossy();
-        let paths: Vec<_> = glob::glob(&full_pat)
-            .context("invalid glob pattern")?
-            .filter_map(Result::ok)
-            .collect();
-        Ok(paths)
+    /// The files to scan, along with the entries which could not be searched
+    pub fn discover_files(&self) -> Result<(Vec<PathBuf>, Vec<SkippedFile>)> {
+        self.source_files(&self.input.dir)
+    }
+
+    /// Resolve the `--base` arguments into files: plain files are taken as is,
+    /// directories are searched with `--pat`, anything else is used as a glob
+    pub fn discover_base_files(
+        &self,
+        bases: &[String],
+    ) -> Result<(Vec<PathBuf>, Vec<SkippedFile>)> {
+        let mut paths = Vec::new();
+        let mut skipped = Vec::new();
+        for base in bases {
+            let path = Path::new(base);
+            if path.is_file() {
+                paths.push(path.to_path_buf());
+                continue;
+            }
+            let (found, errors) = match path.is_dir() {
+                true => self.source_files(path)?,
+                false => glob_files(path)?,
+            };
+            paths.extend(found);
+            skipped.extend(errors);
+        }
+        Ok((paths, skipped))
+    }
+
+    /// The files within a directory matching `--pat`, or without a pattern,
+    /// those with an extension of `--lang` (of any language with `--lang auto`)
+    fn source_files(&self, dir: &Path) -> Result<(Vec<PathBuf>, Vec<SkippedFile>)> {
+        match &self.input.pat {
+            Some(pat) => glob_files(&dir.join(pat)),
+            None => {
+                let (mut paths, skipped) = glob_files(&dir.join("**/*"))?;
+                paths.retain(
+                    |path| match Lang::detect(path, "", &self.fingerprint.lang_map) {
+                        Some(lang) => {
+                            matches!(self.fingerprint.lang, Lang::Auto)
+                                || lang == self.fingerprint.lang
+                        }
+                        None => false,
+                    },
+                );
+                Ok((paths, skipped))
+            }
+        }
+    }
+
+    pub fn generator(&self, lang: Lang) -> FingerPrintGenerator<Box<dyn Preprocessor>> {
+        let preprocessor = lang.preprocessor(&self.fingerprint.preprocess);
+        let kgram: Box<dyn fuscum::kgram::Kgram> = match self.fingerprint.hash {
+            Hash::Rolling => Box::new(default_rolling_kgram()),
+            Hash::Std => Box::new(StdHashKgram),
+        };
+        FingerPrintGenerator {
+            config: FingerPrintConfig::builder()
+                .k(self.fingerprint.kgram_size)
+                .window_size(self.fingerprint.window_size)
+                .robust(self.fingerprint.robust)
+                .unit(self.fingerprint.unit.into())
+                .build(),
+            preprocessor,
+            kgram,
+        }
     }
 
-    pub fn generate_fingerprints(&self, files: &[PathBuf]) -> Result<Vec<Submission>> {
-        let submissions: Vec<Submission> = files
+    /// Read a source file, decoding it as Windows-1252 if it is not valid UTF-8
+    /// and lossy decoding is enabled
+    fn read_source(&self, path: &Path) -> Result<String> {
+        decode(fs::read(path)?, self.input.lossy)
+    }
+
+    /// The language of a file, detected with `--lang auto`
+    fn lang(&self, path: &Path, src: &str) -> Result<Lang> {
+        match self.fingerprint.lang {
+            Lang::Auto => Lang::detect(path, src, &self.fingerprint.lang_map)
+                .context("unknown language (see --lang-map)"),
+            lang => Ok(lang),
+        }
+    }
+
+    /// Read a source file and detect its language
+    pub fn read_file(&self, path: &Path) -> Result<(String, Lang)> {
+        let src = self.read_source(path)?;
+        let lang = self.lang(path, &src)?;
+        Ok((src, lang))
+    }
+
+    /// Hash every k-gram of the base code, as a submission may select any of
+    /// them, not only those the winnowing of the base itself selects
+    pub fn generate_base_hashes(&self, files: &[PathBuf]) -> Result<HashSet<u64>> {
+        files
             .par_iter()
             .map(|path| {
-                let preprocessor = self.args.lang.preprocessor();
-                let kgram: Box<dyn fuscum::kgram::Kgram> = match self.args.hash {
-                    Hash::Rolling => Box::new(default_rolling_kgram()),
-                    Hash::Std => Box::new(StdHashKgram),
-                };
-                let gen = FingerPrintGenerator {
-                    config: FingerPrintConfig::builder()
-                        .k(self.args.kgram_size)
-                        .window_size(self.args.window_size)
-                        .build(),
-                    preprocessor,
-                    kgram,
-                };
-                let name = path.file_name().unwrap().to_string_lossy().to_string();
-                println!("Processing {}", name);
-                let src = fs::read_to_string(path)
-                    .with_context(|| format!("failed to read {}", path.display()))
-                    .unwrap();
-                let fingerprint = gen.generate(&src);
-                Submission { name, fingerprint }
+                let (src, lang) = self
+                    .read_file(path)
+                    .with_context(|| format!("failed to read {}", path.display()))?;
+                Ok(self.generator(lang).kgram_hashes(&src))
+            })
+            .try_reduce(HashSet::new, |mut acc, hashes| {
+                acc.extend(hashes);
+                Ok(acc)
             })
+    }
+
+    /// The path of a file relative to the scanned directory
+    fn relative<'a>(&self, path: &'a Path) -> &'a Path {
+        path.strip_prefix(&self.input.dir).unwrap_or(path)
+    }
+
+    /// The name of the submission a file belongs to, if any
+    fn submission_name(&self, path: &Path, regex: Option<&Regex>) -> Option<String> {
+        let relative = self.relative(path);
+        if let Some(regex) = regex {
+            let relative = relative.to_string_lossy();
+            let captures = regex.captures(&relative)?;
+            let name = captures.get(1).or_else(|| captures.get(0))?;
+            return Some(name.as_str().to_string());
+        }
+        match self.input.group_by {
+            GroupBy::File => Some(relative.to_string_lossy().to_string()),
+            GroupBy::Dir => {
+                let parent = relative.parent()?;
+                let components: Vec<_> = parent
+                    .components()
+                    .filter(|c| matches!(c, Component::Normal(_)))
+                    .take(self.input.group_depth)
+                    .collect();
+                if components.len() < self.input.group_depth {
+                    return None;
+                }
+                let name: PathBuf = components.iter().collect();
+                Some(name.to_string_lossy().to_string())
+            }
+        }
+    }
+
+    /// Group files into submissions, skipping files which belong to none
+    pub fn group_files(&self, files: Vec<PathBuf>) -> Result<BTreeMap<String, Vec<PathBuf>>> {
+        let regex = self
+            .input
+            .group_regex
+            .as_deref()
+            .map(Regex::new)
+            .transpose()
+            .context("invalid grouping regex")?;
+
+        let mut groups: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+        for path in files {
+            match self.submission_name(&path, regex.as_ref()) {
+                Some(name) => groups.entry(name).or_default().push(path),
+                None => println!("Skipping {}: not part of any submission", path.display()),
+            }
+        }
+        Ok(groups)
+    }
+
+    /// Fingerprint the files grouped into submissions.
+    ///
+    /// Files which cannot be read are skipped rather than aborting the scan,
+    /// and so are submissions left without any file.
+    pub fn generate_fingerprints(
+        &self,
+        files: Vec<PathBuf>,
+    ) -> Result<(Vec<Submission>, Vec<SkippedFile>)> {
+        let groups = self.group_files(files)?;
+        let (submissions, skipped): (Vec<_>, Vec<_>) = groups
+            .into_par_iter()
+            .map(|(name, paths)| self.fingerprint_files(name, paths))
+            .unzip();
+        let submissions = submissions
+            .into_iter()
+            .filter(|s| !s.files.is_empty())
             .collect();
-        Ok(submissions)
+        Ok((submissions, skipped.into_iter().flatten().collect()))
+    }
+
+    /// Fingerprint a single file, or the files matching `--pat` within a
+    /// directory, as one submission named after the path
+    pub fn generate_submission(&self, path: &Path) -> Result<(Submission, Vec<SkippedFile>)> {
+        let (files, mut skipped) = match path.is_dir() {
+            true => self.source_files(path)?,
+            false => (vec![path.to_path_buf()], Vec::new()),
+        };
+        let name = path.to_string_lossy().to_string();
+        let (submission, unreadable) = self.fingerprint_files(name, files);
+        skipped.extend(unreadable);
+        Ok((submission, skipped))
+    }
+
+    /// Fingerprint the files of a submission, skipping those which cannot be read
+    fn fingerprint_files(
+        &self,
+        name: String,
+        paths: Vec<PathBuf>,
+    ) -> (Submission, Vec<SkippedFile>) {
+        let mut files = Vec::new();
+        let mut skipped = Vec::new();
+        for path in paths {
+            let file_name = self.relative(&path).to_string_lossy().to_string();
+            match self.read_file(&path) {
+                Ok((src, lang)) => {
+                    println!("Processing {}", file_name);
+                    let generator = self.generator(lang);
+                    let fingerprint = generator.generate(&src);
+                    let functions = match self.fingerprint.functions {
+                        true => generator.functions(&src, &fingerprint),
+                        false => Vec::new(),
+                    };
+                    files.push(SourceFile {
+                        name: file_name,
+                        fingerprint,
+                        functions,
+                        path,
+                        lang,
+                    });
+                }
+                Err(e) => {
+                    let reason = format!("{:#}", e);
+                    println!("Skipping {}: {}", file_name, reason);
+                    skipped.push(SkippedFile { path, reason });
+                }
+            }
+        }
+        (Submission::new(name, files), skipped)
+    }
+}
+
+/// Decode a source as UTF-8, falling back to Windows-1252 if it is not
+/// valid UTF-8 and lossy decoding is enabled
+pub fn decode(bytes: Vec<u8>, lossy: bool) -> Result<String> {
+    match String::from_utf8(bytes) {
+        Ok(src) => Ok(src),
+        Err(e) if lossy => {
+            let (src, _) = encoding_rs::WINDOWS_1252.decode_without_bom_handling(e.as_bytes());
+            Ok(src.into_owned())
+        }
+        Err(e) => Err(e).context("not valid UTF-8 (see --lossy)"),
+    }
+}
+
+/// The files matching a glob pattern, along with the entries which could
+/// not be searched, such as unreadable directories
+fn glob_files(pat: &Path) -> Result<(Vec<PathBuf>, Vec<SkippedFile>)> {
+    let pat = pat.to_string_lossy();
+    let mut paths = Vec::new();
+    let mut skipped = Vec::new();
+    for entry in glob::glob(&pat).context("invalid glob pattern")? {
+        match entry {
+            Ok(path) if path.is_file() => paths.push(path),
+            Ok(_) => {}
+            Err(e) => {
+                let reason = e.error().to_string();
+                println!("Skipping {}: {}", e.path().display(), reason);
+                skipped.push(SkippedFile {
+                    path: e.path().to_path_buf(),
+                    reason,
+                });
+            }
+        }
+    }
+    Ok((paths, skipped))
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::arg::{Cli, Commands};
+
+    fn discovery(args: &[&str]) -> FileDiscovery {
+        let base = [
+            "fuscum-cli",
+            "scan",
+            "subs",
+            "--pat",
+            "**/*",
+            "--lang",
+            "python",
+        ];
+        let cli = Cli::try_parse_from(base.iter().chain(args)).unwrap();
+        let Commands::Scan(args) = cli.command else {
+            unreachable!()
+        };
+        FileDiscovery::new(args.input, args.fingerprint)
+    }
+
+    fn groups(discovery: &FileDiscovery, files: &[&str]) -> Vec<(String, usize)> {
+        let files = files.iter().map(|f| Path::new("subs").join(f)).collect();
+        discovery
+            .group_files(files)
+            .unwrap()
+            .into_iter()
+            .map(|(name, paths)| (name, paths.len()))
+            .collect()
+    }
+
+    const FILES: [&str; 5] = [
+        "alice/hw1/main.py",
+        "alice/hw1/util.py",
+        "alice/hw2/main.py",
+        "bob/hw1/main.py",
+        "README.py",
+    ];
+
+    #[test]
+    fn group_by_file() {
+        let groups = groups(&discovery(&[]), &FILES);
+        assert_eq!(groups.len(), 5);
+        assert_eq!(groups[0], ("README.py".to_string(), 1));
+        assert_eq!(groups[1], ("alice/hw1/main.py".to_string(), 1));
+    }
+
+    #[test]
+    fn group_by_dir() {
+        // files outside of any directory belong to no submission
+        let by_dir = groups(&discovery(&["--group-by", "dir"]), &FILES);
+        assert_eq!(by_dir, [("alice".to_string(), 3), ("bob".to_string(), 1)]);
+
+        let by_depth = groups(
+            &discovery(&["--group-by", "dir", "--group-depth", "2"]),
+            &FILES,
+        );
+        assert_eq!(
+            by_depth,
+            [
+                ("alice/hw1".to_string(), 2),
+                ("alice/hw2".to_string(), 1),
+                ("bob/hw1".to_string(), 1)
+            ]
+        );
+
+        // deeper than any file
+        assert!(groups(
+            &discovery(&["--group-by", "dir", "--group-depth", "3"]),
+            &FILES
+        )
+        .is_empty());
+
+        let base = [
+            "fuscum-cli",
+            "scan",
+            "subs",
+            "--pat",
+            "*",
+            "--lang",
+            "python",
+        ];
+        let zero = Cli::try_parse_from(base.iter().chain(&["--group-depth", "0"]));
+        assert!(zero.is_err());
+    }
+
+    #[test]
+    fn group_by_regex() {
+        // the first capture group names the submission
+        let by_capture = groups(&discovery(&["--group-regex", "^[^/]+/(hw[0-9])/"]), &FILES);
+        assert_eq!(by_capture, [("hw1".to_string(), 3), ("hw2".to_string(), 1)]);
+
+        // or else the whole match; files not matching belong to no submission
+        let by_match = groups(&discovery(&["--group-regex", "^bob/"]), &FILES);
+        assert_eq!(by_match, [("bob/".to_string(), 1)]);
+
+        // and it takes precedence over grouping by directory
+        let both = groups(
+            &discovery(&["--group-by", "dir", "--group-regex", "main"]),
+            &FILES,
+        );
+        assert_eq!(both, [("main".to_string(), 3)]);
+
+        let invalid = discovery(&["--group-regex", "("]);
+        assert!(invalid
+            .group_files(vec![PathBuf::from("subs/a.py")])
+            .is_err());
     }
 }
@@ -0,0 +1,74 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read};
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::arg::FingerprintArgs;
+use crate::discovery::Submission;
+
+/// The version of the on-disk index format, bumped on incompatible changes
+pub const INDEX_VERSION: u32 = 1;
+
+/// Fingerprinted submissions persisted to disk, along with the settings
+/// they were fingerprinted with, so new submissions can be fingerprinted
+/// the same way and compared against them
+#[derive(Serialize, Deserialize)]
+pub struct Index {
+    pub version: u32,
+    pub fingerprint: FingerprintArgs,
+    pub submissions: Vec<Submission>,
+}
+
+#[derive(Deserialize)]
+struct Header {
+    version: u32,
+}
+
+impl Index {
+    /// Keep the submissions along with the settings they were fingerprinted
+    /// with. Their paths are made absolute, so that reports can read the
+    /// files when the index is queried from another directory.
+    pub fn new(fingerprint: FingerprintArgs, mut submissions: Vec<Submission>) -> Self {
+        for file in submissions.iter_mut().flat_map(|s| s.files.iter_mut()) {
+            if let Ok(path) = file.path.canonicalize() {
+                file.path = path;
+            }
+        }
+        Self {
+            version: INDEX_VERSION,
+            fingerprint,
+            submissions,
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut content = String::new();
+        BufReader::new(
+            File::open(path).with_context(|| format!("failed to open {}", path.display()))?,
+        )
+        .read_to_string(&mut content)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+
+        let header: Header = serde_json::from_str(&content)
+            .with_context(|| format!("{} is not a fingerprint index", path.display()))?;
+        if header.version != INDEX_VERSION {
+            bail!(
+                "{} has index version {}, expected {}",
+                path.display(),
+                header.version,
+                INDEX_VERSION
+            );
+        }
+        serde_json::from_str(&content)
+            .with_context(|| format!("failed to parse {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let file =
+            File::create(path).with_context(|| format!("failed to create {}", path.display()))?;
+        serde_json::to_writer(BufWriter::new(file), self)
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+}
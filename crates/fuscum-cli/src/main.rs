@@ -1,41 +1,490 @@
 mod analysis;
 mod arg;
+mod config;
 mod discovery;
+mod index;
 mod output;
+mod report;
 mod summary;
 mod visual;
 
-use anyhow::Result;
-use clap::{CommandFactory, Parser};
+use anyhow::{Context, Result};
+use std::path::PathBuf;
+
+use clap::{CommandFactory, FromArgMatches};
 use clap_complete::generate;
 
-use arg::{Cli, Commands};
+use arg::{Cli, Commands, IndexCommands};
 
 fn run(args: arg::Args) -> Result<()> {
     // Discover files and generate fingerprints
-    let discovery = discovery::FileDiscovery::new(args.clone());
-    let paths = discovery.discover_files()?;
-    let submissions = discovery.generate_fingerprints(&paths)?;
+    let discovery = discovery::FileDiscovery::new(args.input.clone(), args.fingerprint.clone());
+    let (paths, mut skipped) = discovery.discover_files()?;
+    let (mut submissions, unreadable) = discovery.generate_fingerprints(paths)?;
+    skipped.extend(unreadable);
+    let current = submissions.len();
+
+    // Archived submissions are fingerprinted the same way, named after their archive
+    for archive in &args.archive {
+        let input = arg::InputArgs {
+            dir: archive.clone(),
+            ..args.input.clone()
+        };
+        let discovery = discovery::FileDiscovery::new(input, args.fingerprint.clone());
+        let (paths, archive_skipped) = discovery.discover_files()?;
+        skipped.extend(archive_skipped);
+        let (archived, archive_skipped) = discovery.generate_fingerprints(paths)?;
+        skipped.extend(archive_skipped);
+        submissions.extend(archived.into_iter().map(|mut s| {
+            s.name = archive.join(&s.name).to_string_lossy().to_string();
+            s
+        }));
+    }
+
+    // Exclude base code from every submission
+    let (base_paths, base_skipped) = discovery.discover_base_files(&args.base)?;
+    skipped.extend(base_skipped);
+    if !base_paths.is_empty() {
+        let base = discovery.generate_base_hashes(&base_paths)?;
+        let excluded: usize = submissions.iter_mut().map(|s| s.exclude(&base)).sum();
+        println!(
+            "Excluded {} fingerprints matching {} base code hashes",
+            excluded,
+            base.len()
+        );
+    }
+
+    // Ignore hashes common to too many submissions, archived ones included
+    let filter =
+        analysis::FrequencyFilter::new(args.filter.max_submissions, args.filter.max_fraction);
+    if let Some(report) = filter.apply(&mut submissions) {
+        println!(
+            "Discarded {} hashes ({} fingerprints) appearing in more than {} submissions",
+            report.discarded_hashes, report.removed_fingerprints, report.limit
+        );
+    }
+    let archived = submissions.split_off(current);
 
     // Analyze similarities
-    let analyzer = analysis::SimilarityAnalyzer::new(args.threshold, args.top_k);
+    let analyzer = analysis::SimilarityAnalyzer::new(
+        args.analysis.threshold,
+        args.analysis.top_k,
+        args.analysis.metric.into(),
+        args.analysis.cross_lang == arg::CrossLang::Include,
+    );
     let summaries = analyzer.analyze_fingerprints(&submissions)?;
 
     // Handle output
-    let output = output::OutputHandler::new(args);
+    let output = output::OutputHandler::new(args.output, args.analysis.threshold);
     output.print_table(&summaries)?;
     output.write_json(&summaries)?;
     output.write_network(&summaries)?;
+    output.write_report(&summaries, &submissions, &submissions)?;
+    if args.fingerprint.functions {
+        let pairs = analyzer.analyze_functions(&submissions, &submissions);
+        output.write_functions(&pairs)?;
+    }
+    output.write_errors(&skipped)?;
+
+    // Archived submissions are only compared against the current batch
+    if !archived.is_empty() {
+        let summaries = analyzer.analyze_against(&submissions, &archived)?;
+        output.write_archive(
+            &summaries,
+            &submissions,
+            &archived,
+            args.archive_json.as_deref(),
+        )?;
+    }
 
     Ok(())
 }
 
+fn build_index(
+    input: arg::InputArgs,
+    fingerprint: arg::FingerprintArgs,
+    path: &std::path::Path,
+) -> Result<()> {
+    let discovery = discovery::FileDiscovery::new(input, fingerprint.clone());
+    let (paths, mut skipped) = discovery.discover_files()?;
+    let (submissions, unreadable) = discovery.generate_fingerprints(paths)?;
+    skipped.extend(unreadable);
+
+    let count = submissions.len();
+    index::Index::new(fingerprint, submissions).save(path)?;
+    println!("Indexed {} submissions into {}", count, path.display());
+    if !skipped.is_empty() {
+        println!("Skipped {} files", skipped.len());
+    }
+
+    Ok(())
+}
+
+fn query_index(
+    path: &std::path::Path,
+    input: arg::InputArgs,
+    analysis: arg::AnalysisArgs,
+    output: arg::OutputArgs,
+) -> Result<()> {
+    let index = index::Index::load(path)?;
+
+    // Fingerprint the new submissions the same way as the indexed ones
+    let discovery = discovery::FileDiscovery::new(input, index.fingerprint.clone());
+    let (paths, mut skipped) = discovery.discover_files()?;
+    let (submissions, unreadable) = discovery.generate_fingerprints(paths)?;
+    skipped.extend(unreadable);
+
+    let analyzer = analysis::SimilarityAnalyzer::new(
+        analysis.threshold,
+        analysis.top_k,
+        analysis.metric.into(),
+        analysis.cross_lang == arg::CrossLang::Include,
+    );
+    let summaries = analyzer.analyze_against(&submissions, &index.submissions)?;
+
+    let output = output::OutputHandler::new(output, analysis.threshold);
+    output.print_table(&summaries)?;
+    output.write_json(&summaries)?;
+    output.write_network(&summaries)?;
+    output.write_report(&summaries, &submissions, &index.submissions)?;
+    if index.fingerprint.functions {
+        let pairs = analyzer.analyze_functions(&submissions, &index.submissions);
+        output.write_functions(&pairs)?;
+    }
+    output.write_errors(&skipped)?;
+
+    Ok(())
+}
+
+/// Rank the submissions of a corpus by similarity to a single file or directory
+fn query(
+    path: &std::path::Path,
+    corpus: arg::InputArgs,
+    fingerprint: arg::FingerprintArgs,
+    matches: &clap::ArgMatches,
+    analysis: arg::AnalysisArgs,
+    output: arg::OutputArgs,
+) -> Result<()> {
+    // An index fixes the settings the query is fingerprinted with
+    let (fingerprint, mut others, mut skipped) = match corpus.dir.is_file() {
+        true => {
+            let index = index::Index::load(&corpus.dir)?;
+            let conflicts = fingerprint.conflicts(&index.fingerprint, matches);
+            if !conflicts.is_empty() {
+                anyhow::bail!(
+                    "{} was fingerprinted with other settings than {}; leave them out to use its settings",
+                    corpus.dir.display(),
+                    conflicts.join(", ")
+                );
+            }
+            (index.fingerprint, index.submissions, Vec::new())
+        }
+        false => {
+            let discovery = discovery::FileDiscovery::new(corpus.clone(), fingerprint.clone());
+            let (paths, mut skipped) = discovery.discover_files()?;
+            let (others, unreadable) = discovery.generate_fingerprints(paths)?;
+            skipped.extend(unreadable);
+            (fingerprint, others, skipped)
+        }
+    };
+
+    let dir = match path.is_dir() {
+        true => path,
+        false => path.parent().unwrap_or(path),
+    };
+    let input = arg::InputArgs::new(dir.to_path_buf(), corpus.pat.clone(), corpus.lossy);
+    let functions = fingerprint.functions;
+    let discovery = discovery::FileDiscovery::new(input, fingerprint);
+    let (submission, query_skipped) = discovery.generate_submission(path)?;
+    skipped.extend(query_skipped);
+    if submission.files.is_empty() {
+        anyhow::bail!("no file of {} could be fingerprinted", path.display());
+    }
+
+    // The queried files may be part of the corpus, which they trivially match
+    let queried: Vec<PathBuf> = submission
+        .files
+        .iter()
+        .filter_map(|f| f.path.canonicalize().ok())
+        .collect();
+    others.retain(|s| {
+        !s.files
+            .iter()
+            .any(|f| f.path.canonicalize().is_ok_and(|p| queried.contains(&p)))
+    });
+
+    let analyzer = analysis::SimilarityAnalyzer::new(
+        analysis.threshold,
+        analysis.top_k,
+        analysis.metric.into(),
+        analysis.cross_lang == arg::CrossLang::Include,
+    );
+    let submissions = [submission];
+    let summaries = analyzer.analyze_against(&submissions, &others)?;
+
+    let output = output::OutputHandler::new(output, analysis.threshold);
+    output.print_table(&summaries)?;
+    if summaries.is_empty() {
+        println!(
+            "No submission of {} scores at least {}",
+            corpus.dir.display(),
+            analysis.threshold
+        );
+    }
+    output.write_json(&summaries)?;
+    output.write_network(&summaries)?;
+    output.write_report(&summaries, &submissions, &others)?;
+    if functions {
+        let pairs = analyzer.analyze_functions(&submissions, &others);
+        output.write_functions(&pairs)?;
+    }
+    output.write_errors(&skipped)?;
+
+    Ok(())
+}
+
+fn write_config(
+    fingerprint: arg::FingerprintArgs,
+    analysis: arg::AnalysisArgs,
+    filter: arg::FilterArgs,
+    path: Option<&std::path::Path>,
+) -> Result<()> {
+    let config = config::Config::new(&fingerprint, &analysis, &filter).to_toml()?;
+    match path {
+        Some(path) => {
+            std::fs::write(path, config)
+                .with_context(|| format!("failed to write {}", path.display()))?;
+            println!("Config written to {}", path.display());
+        }
+        None => print!("{}", config),
+    }
+    Ok(())
+}
+
+fn compare(
+    paths: [&std::path::Path; 2],
+    pat: Option<String>,
+    lossy: bool,
+    fingerprint: arg::FingerprintArgs,
+    report: Option<PathBuf>,
+) -> Result<()> {
+    let mut sides = Vec::new();
+    for path in paths {
+        // files are named relative to the compared directory, or to their own one
+        let dir = match path.is_dir() {
+            true => path,
+            false => path.parent().unwrap_or(path),
+        };
+        let input = arg::InputArgs::new(dir.to_path_buf(), pat.clone(), lossy);
+        let discovery = discovery::FileDiscovery::new(input, fingerprint.clone());
+        let (submission, _) = discovery.generate_submission(path)?;
+        if submission.files.is_empty() {
+            anyhow::bail!("no file of {} could be fingerprinted", path.display());
+        }
+        sides.push(submission);
+    }
+    let (a, b) = (&sides[0], &sides[1]);
+
+    let (a_hashes, b_hashes) = (a.hashes(), b.hashes());
+    let shared = fuscum::fingerprint::intersection_count(&a_hashes, &b_hashes);
+    let containment = fuscum::fingerprint::Metric::Containment;
+    let a_in_b = containment.score(a_hashes.len(), b_hashes.len(), shared);
+    let b_in_a = containment.score(b_hashes.len(), a_hashes.len(), shared);
+    println!("\n{:<40} {:>12}", "Submission", "Fingerprints");
+    println!("{:<40} {:>12}", a.name, a_hashes.len());
+    println!("{:<40} {:>12}", b.name, b_hashes.len());
+    println!("\nShared fingerprints: {}", shared);
+    println!("{} found in {}: {:.4}", a.name, b.name, a_in_b);
+    println!("{} found in {}: {:.4}", b.name, a.name, b_in_a);
+
+    println!("\nMatched regions");
+    let mut regions = 0;
+    for left in &a.files {
+        for right in &b.files {
+            for m in fuscum::matching::find_matches(&left.fingerprint, &right.fingerprint) {
+                println!(
+                    "  {} lines {}-{} match {} lines {}-{} ({} fingerprints)",
+                    left.name,
+                    m.left.location.start.line,
+                    m.left.location.end.line,
+                    right.name,
+                    m.right.location.start.line,
+                    m.right.location.end.line,
+                    m.fingerprints
+                );
+                regions += 1;
+            }
+        }
+    }
+    if regions == 0 {
+        println!("  none");
+    }
+
+    if let Some(report_dir) = report {
+        let summaries = summary::Summaries::from(vec![summary::Summary {
+            base: a.name.clone(),
+            max_score: a_in_b,
+            against: vec![summary::PairSummary {
+                against: b.name.clone(),
+                score: a_in_b,
+            }],
+        }]);
+        report::Report::new(&summaries, &sides[..1], &sides[1..], 0.0).write(&report_dir)?;
+        println!("\nReport written to {}", report_dir.display());
+    }
+
+    Ok(())
+}
+
+fn inspect(file: &std::path::Path, fingerprint: arg::FingerprintArgs, lossy: bool) -> Result<()> {
+    let input = arg::InputArgs::new(PathBuf::from("."), None, lossy);
+    let unit = fingerprint.unit;
+    let discovery = discovery::FileDiscovery::new(input, fingerprint);
+    let (src, lang) = discovery
+        .read_file(file)
+        .with_context(|| format!("failed to read {}", file.display()))?;
+    let generator = discovery.generator(lang);
+    let fp = generator.generate(&src);
+
+    println!("Language: {}", config::value_name(&lang));
+    // show the units that are actually hashed, tokens and subtrees by the
+    // source they were derived from since only their hashes are kept
+    let units = match unit {
+        arg::Unit::Bytes => {
+            println!(
+                "\nNormalized text:\n{}",
+                generator.preprocessor.preprocess(&src)
+            );
+            None
+        }
+        arg::Unit::Tokens => Some(("Tokens", generator.preprocessor.tokenize(&src))),
+        arg::Unit::Structure => generator
+            .preprocessor
+            .subtrees(&src, generator.config.k)
+            .map(|subtrees| ("Subtrees", subtrees)),
+    };
+    if let Some((name, units)) = units {
+        let lines = fuscum::span::LineIndex::new(&src);
+        println!("\n{name}:");
+        println!("{:>8} {:>16} {:>12}  Source", "Position", "Hash", "Lines");
+        println!("{}", "-".repeat(80));
+        for (position, &hash) in units.tokens.iter().enumerate() {
+            let Some(bytes) = units.offsets.span(position..position + 1) else {
+                continue;
+            };
+            let location = lines.locate(bytes);
+            let lines = format!("{}-{}", location.start.line, location.end.line);
+            let excerpt: String = src[location.bytes].chars().take(60).collect();
+            println!("{:>8} {:016x} {:>12}  {:?}", position, hash, lines, excerpt);
+        }
+    }
+    println!(
+        "\nUnits: {} ({}), k-grams: {} (k = {}), fingerprints: {} (window = {})",
+        fp.preprocessed_len(),
+        config::value_name(&unit),
+        fp.kgram_count(),
+        fp.k(),
+        fp.raw_fingerprint().len(),
+        fp.window_size()
+    );
+
+    println!("\n{:>8} {:>16} {:>12}  Source", "Position", "Hash", "Lines");
+    println!("{}", "-".repeat(80));
+    for &(hash, position) in fp.raw_fingerprint() {
+        let Some(location) = fp.locate_kgram(position) else {
+            continue;
+        };
+        let lines = format!("{}-{}", location.start.line, location.end.line);
+        let excerpt: String = src[location.bytes].chars().take(60).collect();
+        println!("{:>8} {:016x} {:>12}  {:?}", position, hash, lines, excerpt);
+    }
+
+    Ok(())
+}
+
+/// The `--config` file, looked up before parsing since it provides the defaults
+/// of the other options
+fn config_path() -> Option<PathBuf> {
+    let mut args = std::env::args_os();
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg.to_str().and_then(|a| a.strip_prefix("--config=")) {
+            return Some(PathBuf::from(path));
+        }
+    }
+    None
+}
+
 fn main() -> Result<()> {
-    let cli = Cli::parse();
+    let config = config_path()
+        .map(|path| config::Config::load(&path))
+        .transpose()?;
+    let mut cmd = Cli::command();
+    if let Some(config) = &config {
+        cmd = config.apply(cmd);
+    }
+    let matches = cmd.get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
 
     match cli.command {
         Commands::Scan(args) => {
-            run(args)?;
+            run(*args)?;
+        }
+        Commands::Index(command) => match *command {
+            IndexCommands::Build {
+                input,
+                fingerprint,
+                output,
+            } => {
+                build_index(input, fingerprint, &output)?;
+            }
+            IndexCommands::Query {
+                index,
+                input,
+                analysis,
+                output,
+            } => {
+                query_index(&index, input, analysis, output)?;
+            }
+        },
+        Commands::Config {
+            fingerprint,
+            analysis,
+            filter,
+            output,
+        } => {
+            write_config(fingerprint, analysis, filter, output.as_deref())?;
+        }
+        Commands::Query {
+            file,
+            corpus,
+            fingerprint,
+            analysis,
+            output,
+        } => {
+            let matches = matches
+                .subcommand_matches("query")
+                .expect("the query subcommand was parsed");
+            query(&file, corpus, fingerprint, matches, analysis, output)?;
+        }
+        Commands::Compare {
+            a,
+            b,
+            pat,
+            lossy,
+            fingerprint,
+            report,
+        } => {
+            compare([&a, &b], pat, lossy, fingerprint, report)?;
+        }
+        Commands::Inspect {
+            file,
+            fingerprint,
+            lossy,
+        } => {
+            inspect(&file, fingerprint, lossy)?;
         }
         Commands::Completions { shell } => {
             let mut cmd = Cli::command();
@@ -1,19 +1,24 @@
 use std::fs;
+use std::path::Path;
 
 use anyhow::{Context, Result};
 use rinja::Template;
+use serde::Serialize;
 
-use crate::arg::Args;
-use crate::summary::Summaries;
+use crate::arg::OutputArgs;
+use crate::discovery::{SkippedFile, Submission};
+use crate::report::Report;
+use crate::summary::{FunctionPair, FunctionSummary, Summaries};
 use crate::visual::NetworkTemplate;
 
 pub struct OutputHandler {
-    args: Args,
+    args: OutputArgs,
+    threshold: f32,
 }
 
 impl OutputHandler {
-    pub fn new(args: Args) -> Self {
-        Self { args }
+    pub fn new(args: OutputArgs, threshold: f32) -> Self {
+        Self { args, threshold }
     }
 
     pub fn print_table(&self, summaries: &Summaries) -> Result<()> {
@@ -30,17 +35,36 @@ impl OutputHandler {
 
     pub fn write_json(&self, summaries: &Summaries) -> Result<()> {
         if let Some(json_path) = &self.args.json {
-            let json = serde_json::to_string_pretty(&summaries.0)?;
-            fs::write(json_path, json)
-                .with_context(|| format!("failed to write {}", json_path.display()))?;
-            println!("\nJSON written to {}", json_path.display());
+            write_json(&summaries.0, json_path)?;
+        }
+        Ok(())
+    }
+
+    /// Report the best matching functions across submissions
+    pub fn write_functions(&self, pairs: &[FunctionPair]) -> Result<()> {
+        println!("\nMatching functions");
+        println!("{:<40} {:<40} {:>10}", "Function", "Against", "Score");
+        println!("{}", "-".repeat(92));
+        for pair in pairs {
+            let describe = |f: &FunctionSummary| {
+                format!("{}:{} ({}-{})", f.file, f.name, f.start_line, f.end_line)
+            };
+            println!(
+                "{:<40} {:<40} {:>10.4}",
+                describe(&pair.base),
+                describe(&pair.against),
+                pair.score
+            );
+        }
+        if let Some(json_path) = &self.args.functions_json {
+            write_json(&pairs, json_path)?;
         }
         Ok(())
     }
 
     pub fn write_network(&self, summaries: &Summaries) -> Result<()> {
         if let Some(network_path) = &self.args.network {
-            let template = NetworkTemplate::new(summaries, self.args.threshold);
+            let template = NetworkTemplate::new(summaries, self.threshold);
             let rendered = template
                 .render()
                 .context("failed to render network template")?;
@@ -53,4 +77,64 @@ impl OutputHandler {
         }
         Ok(())
     }
+
+    /// Write the HTML report, where the bases of the summaries are looked up in
+    /// `bases` and the submissions they are compared against in `others`
+    pub fn write_report(
+        &self,
+        summaries: &Summaries,
+        bases: &[Submission],
+        others: &[Submission],
+    ) -> Result<()> {
+        if let Some(report_dir) = &self.args.report {
+            Report::new(summaries, bases, others, self.threshold).write(report_dir)?;
+            println!("Report written to {}", report_dir.display());
+        }
+        Ok(())
+    }
+
+    /// Write the files skipped during the scan, if any were
+    pub fn write_errors(&self, skipped: &[SkippedFile]) -> Result<()> {
+        if skipped.is_empty() {
+            return Ok(());
+        }
+        println!("\nSkipped {} files", skipped.len());
+        if let Some(errors_path) = &self.args.errors {
+            let json = serde_json::to_string_pretty(skipped)?;
+            fs::write(errors_path, json)
+                .with_context(|| format!("failed to write {}", errors_path.display()))?;
+            println!("Skipped files written to {}", errors_path.display());
+        }
+        Ok(())
+    }
+
+    /// Report the matches of the current submissions against archived ones,
+    /// separately from the matches among the current submissions
+    pub fn write_archive(
+        &self,
+        summaries: &Summaries,
+        submissions: &[Submission],
+        archived: &[Submission],
+        json_path: Option<&Path>,
+    ) -> Result<()> {
+        println!("\nMatches against archive");
+        self.print_table(summaries)?;
+        if let Some(json_path) = json_path {
+            write_json(&summaries.0, json_path)?;
+        }
+        if let Some(report_dir) = &self.args.report {
+            let archive_dir = report_dir.join("archive");
+            Report::new(summaries, submissions, archived, self.threshold).write(&archive_dir)?;
+            println!("Archive report written to {}", archive_dir.display());
+        }
+        Ok(())
+    }
+}
+
+fn write_json<T: Serialize + ?Sized>(value: &T, json_path: &Path) -> Result<()> {
+    let json = serde_json::to_string_pretty(value)?;
+    fs::write(json_path, json)
+        .with_context(|| format!("failed to write {}", json_path.display()))?;
+    println!("\nJSON written to {}", json_path.display());
+    Ok(())
 }
@@ -0,0 +1,237 @@
+use std::collections::HashMap;
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use fuscum::matching::{find_matches, Match};
+use rinja::Template;
+
+use crate::discovery::{decode, Submission};
+use crate::summary::Summaries;
+use crate::visual::{FilePair, IndexEntry, MatchRow, PairTemplate, ReportIndexTemplate, Segment};
+
+/// A pair of submissions in the report, scored by the higher of both directions
+struct ReportPair<'a> {
+    left: &'a Submission,
+    right: &'a Submission,
+    score: f32,
+}
+
+pub struct Report<'a> {
+    pairs: Vec<ReportPair<'a>>,
+}
+
+impl<'a> Report<'a> {
+    /// Collect the pairs scoring above the threshold. When the submissions were
+    /// compared among themselves (`bases` and `others` are the same), both
+    /// directions of a pair are reported once.
+    pub fn new(
+        summaries: &Summaries,
+        bases: &'a [Submission],
+        others: &'a [Submission],
+        threshold: f32,
+    ) -> Self {
+        let by_name = |submissions: &'a [Submission]| -> HashMap<&'a str, &'a Submission> {
+            submissions.iter().map(|s| (s.name.as_str(), s)).collect()
+        };
+        let (left_by_name, right_by_name) = (by_name(bases), by_name(others));
+        let symmetric = std::ptr::eq(bases, others);
+
+        let mut scores: HashMap<(&str, &str), f32> = HashMap::new();
+        for summary in summaries.iter() {
+            for pair in summary.against.iter().filter(|p| p.score >= threshold) {
+                let key = if !symmetric || summary.base <= pair.against {
+                    (summary.base.as_str(), pair.against.as_str())
+                } else {
+                    (pair.against.as_str(), summary.base.as_str())
+                };
+                let score = scores.entry(key).or_insert(pair.score);
+                *score = score.max(pair.score);
+            }
+        }
+
+        let mut pairs: Vec<ReportPair> = scores
+            .into_iter()
+            .filter_map(|((left, right), score)| {
+                Some(ReportPair {
+                    left: left_by_name.get(left)?,
+                    right: right_by_name.get(right)?,
+                    score,
+                })
+            })
+            .collect();
+        pairs.sort_by(|a, b| {
+            b.score
+                .total_cmp(&a.score)
+                .then_with(|| a.left.name.cmp(&b.left.name))
+                .then_with(|| a.right.name.cmp(&b.right.name))
+        });
+
+        Self { pairs }
+    }
+
+    /// Write the index page and one side-by-side page per pair into `dir`
+    pub fn write(&self, dir: &Path) -> Result<()> {
+        fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+
+        let mut entries = Vec::with_capacity(self.pairs.len());
+        for (i, pair) in self.pairs.iter().enumerate() {
+            let page = format!("pair-{}.html", i + 1);
+            let files = file_pairs(pair.left, pair.right);
+
+            let template = PairTemplate {
+                left_name: &pair.left.name,
+                right_name: &pair.right.name,
+                score: pair.score,
+                files: &files,
+            };
+            let rendered = template
+                .render()
+                .context("failed to render pair template")?;
+            let path = dir.join(&page);
+            fs::write(&path, rendered)
+                .with_context(|| format!("failed to write {}", path.display()))?;
+
+            entries.push(IndexEntry {
+                left: pair.left.name.clone(),
+                right: pair.right.name.clone(),
+                score: pair.score,
+                matches: files.iter().map(|f| f.matches.len()).sum(),
+                page,
+            });
+        }
+
+        let rendered = ReportIndexTemplate { pairs: &entries }
+            .render()
+            .context("failed to render report index template")?;
+        let path = dir.join("index.html");
+        fs::write(&path, rendered).with_context(|| format!("failed to write {}", path.display()))
+    }
+}
+
+/// Read a source the way it was fingerprinted. A submitted file that is not
+/// valid UTF-8 was decoded lossily, so the same fallback is always taken.
+fn read_source(path: &Path) -> Result<String> {
+    fs::read(path)
+        .map_err(anyhow::Error::from)
+        .and_then(|bytes| decode(bytes, true))
+        .with_context(|| format!("failed to read {}", path.display()))
+}
+
+/// Every pair of files across two submissions which share at least one region.
+/// Matches are numbered across all file pairs so they can be linked within a page.
+fn file_pairs(left: &Submission, right: &Submission) -> Vec<FilePair> {
+    let mut pairs = Vec::new();
+    let mut next_id = 0;
+    for l in &left.files {
+        for r in &right.files {
+            let matches = find_matches(&l.fingerprint, &r.fingerprint);
+            if matches.is_empty() {
+                continue;
+            }
+            let first_id = next_id;
+            next_id += matches.len();
+            pairs.push(FilePair {
+                left_name: l.name.clone(),
+                right_name: r.name.clone(),
+                matches: match_rows(&matches, first_id),
+                left: source_segments(
+                    &l.path,
+                    first_id,
+                    matches.iter().map(|m| &m.left.location.bytes),
+                ),
+                right: source_segments(
+                    &r.path,
+                    first_id,
+                    matches.iter().map(|m| &m.right.location.bytes),
+                ),
+            });
+        }
+    }
+    pairs
+}
+
+/// The segments of a source file, or a note in place of its text if it
+/// cannot be read or has changed since it was fingerprinted, so that the
+/// rest of the report is still written
+fn source_segments<'r>(
+    path: &Path,
+    first_id: usize,
+    regions: impl Iterator<Item = &'r Range<usize>> + Clone,
+) -> Vec<Segment> {
+    let note = match read_source(path) {
+        Ok(src) => {
+            let fits = |i: usize| src.is_char_boundary(i);
+            if regions.clone().all(|r| fits(r.start) && fits(r.end)) {
+                return segments(&src, first_id, regions);
+            }
+            format!("{} has changed since it was fingerprinted", path.display())
+        }
+        Err(e) => format!("{e:#}"),
+    };
+    println!(
+        "Leaving out the source of {} from the report: {}",
+        path.display(),
+        note
+    );
+    vec![Segment {
+        text: format!("({note})"),
+        region: None,
+    }]
+}
+
+fn match_rows(matches: &[Match], first_id: usize) -> Vec<MatchRow> {
+    matches
+        .iter()
+        .enumerate()
+        .map(|(i, m)| MatchRow {
+            id: first_id + i,
+            left_lines: (m.left.location.start.line, m.left.location.end.line),
+            right_lines: (m.right.location.start.line, m.right.location.end.line),
+            fingerprints: m.fingerprints,
+        })
+        .collect()
+}
+
+/// Split a source into plain and highlighted segments, where the
+/// `i`-th region is highlighted as match `first_id + i`
+fn segments<'r>(
+    src: &str,
+    first_id: usize,
+    regions: impl Iterator<Item = &'r Range<usize>>,
+) -> Vec<Segment> {
+    let mut regions: Vec<(usize, Range<usize>)> = regions
+        .cloned()
+        .enumerate()
+        .map(|(i, range)| (first_id + i, range))
+        .collect();
+    regions.sort_by_key(|(_, r)| r.start);
+
+    let mut segments = Vec::new();
+    let mut pos = 0;
+    for (id, range) in regions {
+        let start = range.start.max(pos);
+        if start >= range.end {
+            continue;
+        }
+        if pos < start {
+            segments.push(Segment {
+                text: src[pos..start].to_string(),
+                region: None,
+            });
+        }
+        segments.push(Segment {
+            text: src[start..range.end].to_string(),
+            region: Some(id),
+        });
+        pos = range.end;
+    }
+    if pos < src.len() {
+        segments.push(Segment {
+            text: src[pos..].to_string(),
+            region: None,
+        });
+    }
+    segments
+}
@@ -30,3 +30,20 @@ impl From<Vec<Summary>> for Summaries {
         Self(vec)
     }
 }
+
+/// A function of a submission, with its 1-based line range
+#[derive(Debug, Serialize)]
+pub struct FunctionSummary {
+    pub submission: String,
+    pub file: String,
+    pub name: String,
+    pub start_line: usize,
+    pub end_line: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FunctionPair {
+    pub base: FunctionSummary,
+    pub against: FunctionSummary,
+    pub score: f32,
+}
@@ -13,3 +13,48 @@ impl NetworkTemplate<'_> {
         NetworkTemplate { data, threshold }
     }
 }
+
+/// A run of source text, highlighted if it belongs to a matched region
+pub struct Segment {
+    pub text: String,
+    pub region: Option<usize>,
+}
+
+pub struct MatchRow {
+    pub id: usize,
+    pub left_lines: (usize, usize),
+    pub right_lines: (usize, usize),
+    pub fingerprints: usize,
+}
+
+/// Two files of a pair of submissions, with their matched regions highlighted
+pub struct FilePair {
+    pub left_name: String,
+    pub right_name: String,
+    pub matches: Vec<MatchRow>,
+    pub left: Vec<Segment>,
+    pub right: Vec<Segment>,
+}
+
+#[derive(Template)]
+#[template(path = "pair.html")]
+pub struct PairTemplate<'a> {
+    pub left_name: &'a str,
+    pub right_name: &'a str,
+    pub score: f32,
+    pub files: &'a [FilePair],
+}
+
+pub struct IndexEntry {
+    pub left: String,
+    pub right: String,
+    pub score: f32,
+    pub matches: usize,
+    pub page: String,
+}
+
+#[derive(Template)]
+#[template(path = "index.html")]
+pub struct ReportIndexTemplate<'a> {
+    pub pairs: &'a [IndexEntry],
+}
@@ -0,0 +1,34 @@
+use std::fmt;
+
+/// The reasons a source cannot be fingerprinted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// the k-gram size is zero
+    ZeroKgram,
+    /// the winnowing window size is zero
+    ZeroWindow,
+    /// the source has syntax errors, reported as the number of ERROR nodes in its tree
+    Parse { errors: usize },
+    /// no fingerprint was selected, e.g. because the preprocessed source is
+    /// shorter than a single k-gram window
+    Empty,
+    /// structural fingerprints were requested from a preprocessor which does
+    /// not build a syntax tree
+    NoSyntaxTree,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::ZeroKgram => write!(f, "k-gram size must be greater than zero"),
+            Error::ZeroWindow => write!(f, "window size must be greater than zero"),
+            Error::Parse { errors } => write!(f, "source has {} syntax errors", errors),
+            Error::Empty => write!(f, "source is too short to be fingerprinted"),
+            Error::NoSyntaxTree => write!(f, "preprocessor does not build a syntax tree"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -1,9 +1,54 @@
 use typed_builder::TypedBuilder;
 
+use crate::error::{Error, Result};
 use crate::kgram::Kgram;
 use crate::preprocess::Preprocessor;
+use crate::span::{LineIndex, Location, OffsetMap};
 use crate::winnow::winnowing;
 use std::collections::HashSet;
+use std::ops::Range;
+
+/// How the shared hashes of two fingerprints are turned into a similarity score
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Metric {
+    /// `|A ∩ B| / |A|`, the fraction of the base found in the other
+    #[default]
+    Containment,
+    /// `|A ∩ B| / |A ∪ B|`
+    Jaccard,
+    /// `2|A ∩ B| / (|A| + |B|)`, also known as the Sørensen coefficient
+    Dice,
+    /// `|A ∩ B| / min(|A|, |B|)`, the overlap coefficient
+    Overlap,
+    /// `|A ∩ B| / max(|A|, |B|)`, the lower of both containments
+    MinContainment,
+    /// `|A ∩ B| / min(|A|, |B|)`, the higher of both containments
+    MaxContainment,
+}
+
+impl Metric {
+    /// Compute the score from the number of hashes in the base, in the other,
+    /// and shared by both. The score is `0.0` whenever it would be undefined,
+    /// i.e. when one of the fingerprints involved is empty.
+    pub fn score(&self, base: usize, against: usize, shared: usize) -> f32 {
+        let denominator = match self {
+            Metric::Containment => base,
+            Metric::Jaccard => base + against - shared,
+            Metric::Dice => return ratio(2 * shared, base + against),
+            Metric::Overlap | Metric::MaxContainment => base.min(against),
+            Metric::MinContainment => base.max(against),
+        };
+        ratio(shared, denominator)
+    }
+}
+
+fn ratio(numerator: usize, denominator: usize) -> f32 {
+    match denominator {
+        0 => 0.0,
+        _ => numerator as f32 / denominator as f32,
+    }
+}
 
 pub trait WithFingerprint {
     type Hash: std::hash::Hash + Eq;
@@ -13,15 +58,41 @@ pub trait WithFingerprint {
     /// Calculate the similarity between two objects
     /// The similarity is calculated as the intersection of the hashes over the base
     fn similarity<S: WithFingerprint<Hash = Self::Hash>>(&self, against: &S) -> f32 {
+        self.similarity_with(against, Metric::Containment)
+    }
+
+    /// Calculate the similarity between two objects with the given metric
+    fn similarity_with<S: WithFingerprint<Hash = Self::Hash>>(
+        &self,
+        against: &S,
+        metric: Metric,
+    ) -> f32 {
         let base = self.fingerprint();
         let against = against.fingerprint();
 
-        let intersection = base.intersection(&against).count() as f32;
-        intersection / base.len() as f32
+        let shared = base.intersection(&against).count();
+        metric.score(base.len(), against.len(), shared)
     }
 }
 
-#[derive(TypedBuilder)]
+/// What the k-grams of a fingerprint are formed over
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Unit {
+    /// bytes of the preprocessed text
+    #[default]
+    Bytes,
+    /// tokens of the source, e.g. the leaves of the syntax tree for the
+    /// language preprocessors, which makes k-grams independent of token length
+    Tokens,
+    /// shapes of the subtrees of the syntax tree, each hashed on its own
+    /// regardless of the order of its children, so that reordered statements
+    /// still match; `k` is the minimum number of nodes of a subtree
+    Structure,
+}
+
+#[derive(TypedBuilder, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FingerPrintConfig {
     /// the size of the k-grams to hash
     #[builder(default = 35)]
@@ -33,6 +104,10 @@ pub struct FingerPrintConfig {
     /// which reduces fingerprint size when long low-entropy sequences are present
     #[builder(default = false)]
     pub robust: bool,
+    /// what the k-grams are formed over
+    #[builder(default)]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub unit: Unit,
 }
 
 impl Default for FingerPrintConfig {
@@ -41,9 +116,58 @@ impl Default for FingerPrintConfig {
     }
 }
 
-#[derive(Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(from = "StoredFingerPrint"))]
 pub struct FingerPrint {
     raw_fingerprint: Vec<(u64, usize)>,
+    /// the distinct hashes of the raw fingerprint, sorted;
+    /// derived from the raw fingerprint, so not stored
+    #[cfg_attr(feature = "serde", serde(skip))]
+    hashes: Vec<u64>,
+    /// the size of the k-grams the positions refer to
+    k: usize,
+    /// the winnowing window the fingerprint was selected with
+    window_size: usize,
+    /// the region of the original source covered by the k-gram of each raw fingerprint
+    locations: Vec<Location>,
+    /// the number of units of the preprocessed source
+    units: usize,
+}
+
+/// The stored fields of a [`FingerPrint`], from which the sorted hashes are rebuilt
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct StoredFingerPrint {
+    raw_fingerprint: Vec<(u64, usize)>,
+    k: usize,
+    window_size: usize,
+    locations: Vec<Location>,
+    units: usize,
+}
+
+#[cfg(feature = "serde")]
+impl From<StoredFingerPrint> for FingerPrint {
+    fn from(stored: StoredFingerPrint) -> Self {
+        Self {
+            hashes: sorted_hashes(&stored.raw_fingerprint),
+            raw_fingerprint: stored.raw_fingerprint,
+            k: stored.k,
+            window_size: stored.window_size,
+            locations: stored.locations,
+            units: stored.units,
+        }
+    }
+}
+
+/// A function of a source along with the part of the source's fingerprint
+/// selected within it
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FunctionFingerPrint {
+    pub name: String,
+    pub location: Location,
+    pub fingerprint: FingerPrint,
 }
 
 pub struct FingerPrintGenerator<P: Preprocessor> {
@@ -54,25 +178,269 @@ pub struct FingerPrintGenerator<P: Preprocessor> {
 
 impl<P: Preprocessor> FingerPrintGenerator<P> {
     pub fn generate<S: AsRef<str>>(&self, src: S) -> FingerPrint {
-        let preprocessed = self.preprocessor.preprocess(src.as_ref());
-        let k_grams = self.kgram.k_gram(preprocessed.as_bytes(), self.config.k);
+        let src = src.as_ref();
+        let (k_grams, offsets, _) = self.k_grams(src).unwrap_or_default();
+        self.fingerprint(src, k_grams, offsets)
+    }
+
+    /// Every k-gram hash of the source rather than only the selected ones,
+    /// e.g. for base code, of which a submission may select any k-gram
+    pub fn kgram_hashes<S: AsRef<str>>(&self, src: S) -> HashSet<u64> {
+        let (k_grams, _, _) = self.k_grams(src.as_ref()).unwrap_or_default();
+        k_grams.into_iter().collect()
+    }
+
+    /// Hash the k-grams of the source in the configured unit, returning them
+    /// along with the offsets of each unit and the number of syntax errors
+    fn k_grams(&self, src: &str) -> Result<(Vec<u64>, OffsetMap, usize)> {
+        Ok(match self.config.unit {
+            Unit::Bytes => {
+                let preprocessed = self.preprocessor.preprocess_mapped(src);
+                let k_grams = self
+                    .kgram
+                    .k_gram(preprocessed.text.as_bytes(), self.config.k);
+                (k_grams, preprocessed.offsets, preprocessed.errors)
+            }
+            Unit::Tokens => {
+                let tokenized = self.preprocessor.tokenize(src);
+                let k_grams = self.kgram.k_gram_tokens(&tokenized.tokens, self.config.k);
+                (k_grams, tokenized.offsets, tokenized.errors)
+            }
+            // every subtree is a k-gram of its own
+            Unit::Structure => {
+                let subtrees = self
+                    .preprocessor
+                    .subtrees(src, self.config.k)
+                    .ok_or(Error::NoSyntaxTree)?;
+                (subtrees.tokens, subtrees.offsets, subtrees.errors)
+            }
+        })
+    }
+
+    fn fingerprint(&self, src: &str, k_grams: Vec<u64>, offsets: OffsetMap) -> FingerPrint {
         let fingerprints = winnowing(k_grams, self.config.window_size, self.config.robust);
+        let k = match self.config.unit {
+            Unit::Structure => 1,
+            _ => self.config.k,
+        };
+        // only the selected k-grams are resolved, the offsets of every unit are dropped
+        let lines = LineIndex::new(src);
+        let locations = fingerprints
+            .iter()
+            .map(|&(_, position)| {
+                let end = (position + k).min(offsets.len());
+                lines.locate(offsets.span(position..end).unwrap_or_default())
+            })
+            .collect();
         FingerPrint {
+            hashes: sorted_hashes(&fingerprints),
             raw_fingerprint: fingerprints,
+            k,
+            window_size: self.config.window_size,
+            locations,
+            units: offsets.len(),
+        }
+    }
+
+    /// Generate the fingerprint of a source, rejecting invalid configurations,
+    /// sources with syntax errors, and sources too short to select any fingerprint
+    pub fn try_generate<S: AsRef<str>>(&self, src: S) -> Result<FingerPrint> {
+        if self.config.k == 0 {
+            return Err(Error::ZeroKgram);
+        }
+        if self.config.window_size == 0 {
+            return Err(Error::ZeroWindow);
         }
+        let src = src.as_ref();
+        let (k_grams, offsets, errors) = self.k_grams(src)?;
+        if errors > 0 {
+            return Err(Error::Parse { errors });
+        }
+        let fingerprint = self.fingerprint(src, k_grams, offsets);
+        if fingerprint.raw_fingerprint.is_empty() {
+            return Err(Error::Empty);
+        }
+        Ok(fingerprint)
     }
+
+    /// Split the fingerprint of a source into the fingerprints of its functions,
+    /// keeping only those functions some fingerprint was selected within
+    pub fn functions<S: AsRef<str>>(
+        &self,
+        src: S,
+        fingerprint: &FingerPrint,
+    ) -> Vec<FunctionFingerPrint> {
+        let lines = LineIndex::new(src.as_ref());
+        self.preprocessor
+            .functions(src.as_ref())
+            .into_iter()
+            .map(|function| FunctionFingerPrint {
+                location: lines.locate(function.bytes.clone()),
+                fingerprint: fingerprint.within(function.bytes),
+                name: function.name,
+            })
+            .filter(|function| !function.fingerprint.raw_fingerprint.is_empty())
+            .collect()
+    }
+}
+
+fn sorted_hashes(raw_fingerprint: &[(u64, usize)]) -> Vec<u64> {
+    let mut hashes: Vec<u64> = raw_fingerprint.iter().map(|(hash, _)| *hash).collect();
+    hashes.sort_unstable();
+    hashes.dedup();
+    hashes
+}
+
+/// Count the values shared by two sorted, deduplicated slices
+pub fn intersection_count(a: &[u64], b: &[u64]) -> usize {
+    let (mut i, mut j, mut count) = (0, 0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            std::cmp::Ordering::Less => i += 1,
+            std::cmp::Ordering::Greater => j += 1,
+            std::cmp::Ordering::Equal => {
+                count += 1;
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    count
 }
 
 impl FingerPrint {
     /// Return the fingerprint as a set of hashes, without their positions
     pub fn fingerprint(&self) -> HashSet<u64> {
-        self.raw_fingerprint.iter().map(|(hash, _)| *hash).collect()
+        self.hashes.iter().copied().collect()
+    }
+
+    /// Return the distinct hashes of the fingerprint in ascending order
+    pub fn hashes(&self) -> &[u64] {
+        &self.hashes
+    }
+
+    /// Calculate the similarity against another fingerprint with the given metric,
+    /// like [`WithFingerprint::similarity_with`] but without building hash sets
+    pub fn compare(&self, against: &FingerPrint, metric: Metric) -> f32 {
+        let shared = intersection_count(&self.hashes, &against.hashes);
+        metric.score(self.hashes.len(), against.hashes.len(), shared)
     }
 
     /// Return the raw fingerprints with their positions
     pub fn raw_fingerprint(&self) -> &[(u64, usize)] {
         &self.raw_fingerprint
     }
+
+    /// Drop every raw fingerprint whose hash is in `hashes`,
+    /// e.g. the fingerprint of base code shared by all submissions.
+    /// Returns the number of raw fingerprints removed.
+    pub fn exclude(&mut self, hashes: &HashSet<u64>) -> usize {
+        let before = self.raw_fingerprint.len();
+        let (raw_fingerprint, locations) = self
+            .raw_fingerprint
+            .drain(..)
+            .zip(self.locations.drain(..))
+            .filter(|((hash, _), _)| !hashes.contains(hash))
+            .unzip();
+        self.raw_fingerprint = raw_fingerprint;
+        self.locations = locations;
+        self.hashes.retain(|hash| !hashes.contains(hash));
+        before - self.raw_fingerprint.len()
+    }
+
+    /// The size of the k-grams the raw fingerprint positions refer to
+    pub fn k(&self) -> usize {
+        self.k
+    }
+
+    /// The winnowing window size the fingerprint was selected with
+    pub fn window_size(&self) -> usize {
+        self.window_size
+    }
+
+    /// The number of units (bytes or tokens) of the preprocessed source
+    pub fn preprocessed_len(&self) -> usize {
+        self.units
+    }
+
+    /// The number of k-grams of the preprocessed source, which the
+    /// fingerprints were selected from
+    pub fn kgram_count(&self) -> usize {
+        (self.preprocessed_len() + 1).saturating_sub(self.k)
+    }
+
+    /// Resolve a run of positions in the preprocessed source to the region of
+    /// the original source covered by the selected k-grams lying within it,
+    /// or none if no k-gram was selected there
+    pub fn locate(&self, positions: Range<usize>) -> Option<Location> {
+        let mut covered = self
+            .raw_fingerprint
+            .iter()
+            .zip(&self.locations)
+            .filter(|&(&(_, position), _)| {
+                positions.start <= position && (position + self.k).min(self.units) <= positions.end
+            })
+            .map(|(_, location)| location);
+        let first = covered.next()?;
+        let (start, end) = covered.fold((first, first), |(start, end), location| {
+            (
+                match location.bytes.start < start.bytes.start {
+                    true => location,
+                    false => start,
+                },
+                match location.bytes.end > end.bytes.end {
+                    true => location,
+                    false => end,
+                },
+            )
+        });
+        Some(Location {
+            bytes: start.bytes.start..end.bytes.end,
+            start: start.start,
+            end: end.end,
+        })
+    }
+
+    /// Resolve the k-gram starting at a raw fingerprint position
+    /// to the region of the original source it covers
+    pub fn locate_kgram(&self, position: usize) -> Option<Location> {
+        // raw fingerprints are selected in order of position
+        let i = self.raw_fingerprint.partition_point(|&(_, p)| p < position);
+        match self.raw_fingerprint.get(i) {
+            Some(&(_, p)) if p == position => Some(self.locations[i].clone()),
+            _ => None,
+        }
+    }
+
+    /// The part of the fingerprint derived from the given range of the
+    /// original source, i.e. the selected k-grams lying entirely within it
+    pub fn within(&self, bytes: Range<usize>) -> FingerPrint {
+        let (raw_fingerprint, locations): (Vec<_>, Vec<_>) = self
+            .raw_fingerprint
+            .iter()
+            .zip(&self.locations)
+            .filter(|(_, location)| {
+                bytes.start <= location.bytes.start && location.bytes.end <= bytes.end
+            })
+            .map(|(&raw, location)| (raw, location.clone()))
+            .unzip();
+        FingerPrint {
+            hashes: sorted_hashes(&raw_fingerprint),
+            raw_fingerprint,
+            k: self.k,
+            window_size: self.window_size,
+            locations,
+            units: self.units,
+        }
+    }
+
+    /// Return the raw fingerprints along with the region of the original source they cover
+    pub fn locations(&self) -> impl Iterator<Item = (u64, Location)> + '_ {
+        self.raw_fingerprint
+            .iter()
+            .zip(&self.locations)
+            .map(|(&(hash, _), location)| (hash, location.clone()))
+    }
 }
 
 impl WithFingerprint for FingerPrint {
@@ -105,6 +473,138 @@ mod tests {
         insta::assert_debug_snapshot!(fp.raw_fingerprint());
     }
 
+    #[test]
+    fn try_generate_rejects() {
+        let gen = |k, window_size| FingerPrintGenerator {
+            config: FingerPrintConfig::builder()
+                .k(k)
+                .window_size(window_size)
+                .build(),
+            preprocessor: RegexPreprocessor::whitespace(),
+            kgram: Box::new(default_rolling_kgram()),
+        };
+        let src = "how much wood could a woodchuck chuck";
+
+        assert_eq!(gen(0, 3).try_generate(src), Err(Error::ZeroKgram));
+        assert_eq!(gen(3, 0).try_generate(src), Err(Error::ZeroWindow));
+        assert_eq!(gen(3, 3).try_generate("wood"), Err(Error::Empty));
+        assert_eq!(gen(3, 3).try_generate(src), Ok(gen(3, 3).generate(src)));
+        // the infallible path must not panic on a zero k-gram size
+        assert!(gen(0, 3).generate(src).raw_fingerprint().is_empty());
+    }
+
+    #[cfg(feature = "ast")]
+    #[test]
+    fn try_generate_parse_errors() {
+        use crate::preprocess::PythonPreprocessor;
+
+        let gen = FingerPrintGenerator {
+            config: FingerPrintConfig::builder().k(3).window_size(3).build(),
+            preprocessor: PythonPreprocessor::default(),
+            kgram: Box::new(default_rolling_kgram()),
+        };
+        assert!(gen.try_generate("def f(a, b):\n\treturn a + b").is_ok());
+        assert!(matches!(
+            gen.try_generate("def f(a, b:\n\treturn a + b"),
+            Err(Error::Parse { errors }) if errors > 0
+        ));
+
+        // tree-sitter recovers from a missing token without an ERROR node
+        let gen = FingerPrintGenerator {
+            config: FingerPrintConfig::builder().k(3).window_size(3).build(),
+            preprocessor: crate::preprocess::CPreprocessor::default(),
+            kgram: Box::new(default_rolling_kgram()),
+        };
+        assert!(gen.try_generate("int main() { return 0; }").is_ok());
+        assert!(matches!(
+            gen.try_generate("int main() { return 0 }"),
+            Err(Error::Parse { errors }) if errors > 0
+        ));
+    }
+
+    #[cfg(feature = "ast")]
+    #[test]
+    fn token_unit() {
+        use crate::preprocess::PythonPreprocessor;
+
+        let gen = |unit| FingerPrintGenerator {
+            config: FingerPrintConfig::builder()
+                .k(4)
+                .window_size(2)
+                .unit(unit)
+                .build(),
+            preprocessor: PythonPreprocessor::default(),
+            kgram: Box::new(default_rolling_kgram()),
+        };
+        let src = "def function(alpha, beta):\n    return alpha + beta\n";
+
+        let bytes = gen(Unit::Bytes).generate(src);
+        let tokens = gen(Unit::Tokens).generate(src);
+        assert_ne!(bytes.fingerprint(), tokens.fingerprint());
+        // def v ( v , v ) : return v + v
+        assert_eq!(tokens.preprocessed_len(), 12);
+        assert_eq!(tokens.kgram_count(), 9);
+        // the first k-gram is `def function ( alpha`
+        assert_eq!(tokens.locate_kgram(0).unwrap().bytes, 0..18);
+    }
+
+    #[cfg(feature = "ast")]
+    #[test]
+    fn structure_unit() {
+        use crate::preprocess::PythonPreprocessor;
+
+        let gen = FingerPrintGenerator {
+            config: FingerPrintConfig::builder()
+                .k(4)
+                .window_size(1)
+                .unit(Unit::Structure)
+                .build(),
+            preprocessor: PythonPreprocessor::default(),
+            kgram: Box::new(default_rolling_kgram()),
+        };
+        // every subtree is selected, so only the set of shapes matters
+        let a = gen.generate("def f(a):\n    x = a * 2\n    y = a - 1\n    return x + y\n");
+        let b = gen.generate("def g(b):\n    q = b - 1\n    p = b * 2\n    return p + q\n");
+        assert_eq!(a.compare(&b, Metric::Jaccard), 1.0);
+        assert_eq!(a.k(), 1);
+
+        let text = FingerPrintGenerator {
+            config: gen.config,
+            preprocessor: RegexPreprocessor::whitespace(),
+            kgram: gen.kgram,
+        };
+        assert_eq!(text.try_generate("x = 1"), Err(Error::NoSyntaxTree));
+    }
+
+    #[cfg(feature = "ast")]
+    #[test]
+    fn function_fingerprints() {
+        use crate::preprocess::PythonPreprocessor;
+
+        let gen = FingerPrintGenerator {
+            config: FingerPrintConfig::builder().k(5).window_size(4).build(),
+            preprocessor: PythonPreprocessor::default(),
+            kgram: Box::new(default_rolling_kgram()),
+        };
+        let copied = "def add(a, b):\n    total = a + b\n    return total * 2\n";
+        let src = format!("import os\n\ndef main():\n    print(os.getcwd())\n\n{copied}");
+        let fp = gen.generate(&src);
+        let functions = gen.functions(&src, &fp);
+        assert_eq!(functions.len(), 2);
+        assert_eq!(functions[1].name, "add");
+        assert_eq!(functions[1].location.start.line, 6);
+        assert_eq!(functions[1].location.end.line, 8);
+
+        // every k-gram of a function is resolved within the function
+        let add = &functions[1];
+        for (_, location) in add.fingerprint.locations() {
+            assert!(add.location.bytes.start <= location.bytes.start);
+            assert!(location.bytes.end <= add.location.bytes.end);
+        }
+        let alone = gen.generate(copied);
+        assert!(add.fingerprint.compare(&alone, Metric::Containment) > 0.5);
+    }
+
     #[test]
     fn with_text_preprocessor() {
         let src = "how much wood could a woodchuck chuck";
@@ -117,4 +617,150 @@ mod tests {
         let fp = gen.generate(src);
         insta::assert_debug_snapshot!(fp.raw_fingerprint());
     }
+
+    #[test]
+    fn metrics() {
+        assert_eq!(Metric::Containment.score(4, 8, 2), 0.5);
+        assert_eq!(Metric::Jaccard.score(4, 8, 2), 0.2);
+        assert_eq!(Metric::Dice.score(4, 8, 2), 4.0 / 12.0);
+        assert_eq!(Metric::Overlap.score(4, 8, 2), 0.5);
+        assert_eq!(Metric::MinContainment.score(4, 8, 2), 0.25);
+        assert_eq!(Metric::MaxContainment.score(8, 4, 2), 0.5);
+    }
+
+    #[test]
+    fn compare_matches_set_similarity() {
+        let gen = FingerPrintGenerator {
+            config: FingerPrintConfig::builder().k(4).window_size(3).build(),
+            preprocessor: RegexPreprocessor::whitespace(),
+            kgram: Box::new(default_rolling_kgram()),
+        };
+        let a = gen.generate("how much wood could a woodchuck chuck");
+        let b = gen.generate("if a woodchuck could chuck wood");
+
+        assert!(a.hashes().windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(a.hashes().len(), a.fingerprint().len());
+        for metric in [Metric::Containment, Metric::Jaccard, Metric::Dice] {
+            assert_eq!(a.compare(&b, metric), a.similarity_with(&b, metric));
+            assert_eq!(b.compare(&a, metric), b.similarity_with(&a, metric));
+        }
+    }
+
+    #[test]
+    fn intersection_of_sorted_hashes() {
+        assert_eq!(intersection_count(&[1, 3, 5, 7], &[2, 3, 4, 7, 9]), 2);
+        assert_eq!(intersection_count(&[], &[1, 2]), 0);
+    }
+
+    #[test]
+    fn empty_fingerprint_similarity() {
+        let gen = FingerPrintGenerator {
+            config: FingerPrintConfig::default(),
+            preprocessor: RegexPreprocessor::whitespace(),
+            kgram: Box::new(default_rolling_kgram()),
+        };
+        let empty = gen.generate("");
+        let other = gen.generate("a".repeat(100));
+
+        assert_eq!(empty.similarity(&other), 0.0);
+        assert_eq!(other.similarity(&empty), 0.0);
+        assert_eq!(empty.similarity_with(&empty, Metric::Jaccard), 0.0);
+    }
+
+    #[test]
+    fn exclude_base() {
+        let gen = FingerPrintGenerator {
+            config: FingerPrintConfig::builder().k(5).window_size(4).build(),
+            preprocessor: RegexPreprocessor::whitespace(),
+            kgram: Box::new(default_rolling_kgram()),
+        };
+        let base = gen.generate("fn main() { println!(\"hello\"); }");
+        let mut submission = gen.generate("fn main() { println!(\"hello\"); } fn extra() { 42 }");
+
+        let removed = submission.exclude(&base.fingerprint());
+        assert!(removed > 0);
+        assert!(submission.fingerprint().is_disjoint(&base.fingerprint()));
+        assert_eq!(submission.compare(&base, Metric::Containment), 0.0);
+        assert!(!submission.fingerprint().is_empty());
+    }
+
+    #[test]
+    fn exclude_base_kgrams() {
+        let gen = FingerPrintGenerator {
+            config: FingerPrintConfig::builder().k(5).window_size(4).build(),
+            preprocessor: RegexPreprocessor::whitespace(),
+            kgram: Box::new(default_rolling_kgram()),
+        };
+        let base_src = "fn main() { println!(\"hello\"); return 0; }";
+        // a piece of the base between other code, which the submission selects
+        // a k-gram of that the winnowing of the whole base did not
+        let submission_src = "let other = compute(1, 2); (\"hello\" while x";
+        let selected = gen.generate(base_src).fingerprint();
+        let all = gen.kgram_hashes(base_src);
+        assert!(selected.is_subset(&all));
+
+        let mut submission = gen.generate(submission_src);
+        submission.exclude(&selected);
+        assert!(!submission.fingerprint().is_disjoint(&all));
+
+        let mut submission = gen.generate(submission_src);
+        assert!(submission.exclude(&all) > 0);
+        assert!(submission.fingerprint().is_disjoint(&all));
+        assert!(!submission.fingerprint().is_empty());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_roundtrip() {
+        let gen = FingerPrintGenerator {
+            config: FingerPrintConfig::builder().k(4).window_size(3).build(),
+            preprocessor: RegexPreprocessor::whitespace(),
+            kgram: Box::new(default_rolling_kgram()),
+        };
+        let fp = gen.generate("how much\nwood could\na woodchuck chuck");
+
+        let json = serde_json::to_string(&fp).unwrap();
+        assert!(!json.contains("\"hashes\""));
+        let restored: FingerPrint = serde_json::from_str(&json).unwrap();
+        assert_eq!(fp, restored);
+        assert_eq!(fp.hashes(), restored.hashes());
+        assert_eq!(fp.locate(0..20), restored.locate(0..20));
+
+        let json = serde_json::to_string(&gen.config).unwrap();
+        let restored: FingerPrintConfig = serde_json::from_str(&json).unwrap();
+        assert_eq!(gen.config, restored);
+    }
+
+    #[test]
+    fn locate_original_source() {
+        let src = "how much\nwood could\na woodchuck chuck";
+        let gen = FingerPrintGenerator {
+            config: FingerPrintConfig::builder().k(4).window_size(3).build(),
+            preprocessor: RegexPreprocessor::whitespace(),
+            kgram: Box::new(default_rolling_kgram()),
+        };
+
+        let fp = gen.generate(src);
+        for (hash, location) in fp.locations() {
+            let original = &src[location.bytes.clone()];
+            let stripped: String = original.chars().filter(|c| !c.is_whitespace()).collect();
+            assert_eq!(stripped.len(), 4, "{hash} covers {original:?}");
+        }
+
+        // k-grams straddling a line break span both lines
+        let (_, loc) = fp
+            .locations()
+            .find(|(_, loc)| loc.start.line == 1 && loc.end.line == 2)
+            .unwrap();
+        assert!(src[loc.bytes.clone()].contains('\n'));
+        assert_eq!(loc.start.column, loc.bytes.start + 1);
+
+        // a run of positions covers the selected k-grams within it
+        let &(_, first) = fp.raw_fingerprint().first().unwrap();
+        let &(_, last) = fp.raw_fingerprint().last().unwrap();
+        let all = fp.locate(first..last + fp.k()).unwrap();
+        assert_eq!(all.start, fp.locate_kgram(first).unwrap().start);
+        assert_eq!(all.end, fp.locate_kgram(last).unwrap().end);
+        assert_eq!(fp.locate_kgram(first + 1), None);
+    }
 }
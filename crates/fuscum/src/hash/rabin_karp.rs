@@ -11,7 +11,7 @@ pub struct RabinKarp<const B: u64, const M: u64> {
 impl<const B: u64, const M: u64> RabinKarp<B, M> {
     /// Creates a new Rabin-Karp hasher with the given window size k, base, and modulus.
     pub fn new(k: usize) -> Self {
-        let high_power = B.powm((k - 1) as u64, &M);
+        let high_power = B.powm(k.saturating_sub(1) as u64, &M);
 
         Self {
             k,
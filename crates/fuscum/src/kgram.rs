@@ -1,20 +1,38 @@
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
 
+use num_modular::{ModularCoreOps, ModularPow};
+
 use crate::hash::rabin_karp::RabinKarp;
 use crate::hash::rolling_hasher::RollingHashIter;
 
 pub trait Kgram {
     fn k_gram(&self, data: &[u8], k: usize) -> Vec<u64>;
+
+    /// Hash every run of `k` consecutive tokens.
+    ///
+    /// By default, the bytes of the tokens are hashed with [`Kgram::k_gram`],
+    /// keeping only the windows which start at a token.
+    fn k_gram_tokens(&self, tokens: &[u64], k: usize) -> Vec<u64> {
+        const TOKEN_SIZE: usize = std::mem::size_of::<u64>();
+        if k == 0 {
+            return Vec::new();
+        }
+        let bytes: Vec<u8> = tokens.iter().flat_map(|t| t.to_le_bytes()).collect();
+        self.k_gram(&bytes, k * TOKEN_SIZE)
+            .into_iter()
+            .step_by(TOKEN_SIZE)
+            .collect()
+    }
 }
 
 pub struct StdHashKgram;
 
 impl Kgram for StdHashKgram {
     fn k_gram(&self, data: &[u8], k: usize) -> Vec<u64> {
-        let mut hashes = Vec::with_capacity(data.len().saturating_sub(k - 1));
+        let mut hashes = Vec::with_capacity((data.len() + 1).saturating_sub(k));
 
-        if k > data.len() {
+        if k == 0 || k > data.len() {
             return hashes;
         }
 
@@ -26,17 +44,57 @@ impl Kgram for StdHashKgram {
 
         hashes
     }
+
+    fn k_gram_tokens(&self, tokens: &[u64], k: usize) -> Vec<u64> {
+        if k == 0 {
+            return Vec::new();
+        }
+        tokens
+            .windows(k)
+            .map(|window| {
+                let mut hasher = DefaultHasher::new();
+                window.hash(&mut hasher);
+                hasher.finish()
+            })
+            .collect()
+    }
 }
 
 pub struct RollingHashKgram<const B: u64, const M: u64>;
 
 impl<const B: u64, const M: u64> Kgram for RollingHashKgram<B, M> {
     fn k_gram(&self, data: &[u8], k: usize) -> Vec<u64> {
+        if k == 0 {
+            return Vec::new();
+        }
         let hasher: RabinKarp<B, M> = RabinKarp::new(k);
         RollingHashIter::new(data, hasher)
             .map(|(_, hash)| hash)
             .collect()
     }
+
+    /// The same polynomial hash as over bytes, with token ids as the digits
+    fn k_gram_tokens(&self, tokens: &[u64], k: usize) -> Vec<u64> {
+        if k == 0 || k > tokens.len() {
+            return Vec::new();
+        }
+        let high_power = B.powm(k as u64 - 1, &M);
+        let digits: Vec<u64> = tokens.iter().map(|token| token % M).collect();
+
+        let mut hash = digits[..k]
+            .iter()
+            .fold(0, |hash: u64, &digit| hash.mulm(B, &M).addm(digit, &M));
+        let mut hashes = Vec::with_capacity(tokens.len() - k + 1);
+        hashes.push(hash);
+        for (&leaving, &entering) in digits.iter().zip(&digits[k..]) {
+            hash = hash
+                .subm(leaving.mulm(high_power, &M), &M)
+                .mulm(B, &M)
+                .addm(entering, &M);
+            hashes.push(hash);
+        }
+        hashes
+    }
 }
 
 pub fn default_rolling_kgram() -> RollingHashKgram<257, { u64::MAX }> {
@@ -63,4 +121,63 @@ mod tests {
         let result = r.k_gram(text.as_bytes(), k);
         insta::assert_debug_snapshot!(result);
     }
+
+    #[test]
+    fn empty_k_gram() {
+        let text = "adorunrunrunadorunrun".as_bytes();
+        let r: RollingHashKgram<257, { u64::MAX }> = RollingHashKgram;
+        assert!(StdHashKgram.k_gram(text, 0).is_empty());
+        assert!(r.k_gram(text, 0).is_empty());
+    }
+
+    #[test]
+    fn token_k_gram() {
+        let tokens = [1, 2, 3, 1, 2, 3];
+        let r: RollingHashKgram<257, { u64::MAX }> = RollingHashKgram;
+        for kgram in [&StdHashKgram as &dyn Kgram, &r] {
+            let result = kgram.k_gram_tokens(&tokens, 3);
+            assert_eq!(result.len(), 4);
+            assert_eq!(result[0], result[3]);
+            assert_ne!(result[0], result[1]);
+            assert!(kgram.k_gram_tokens(&tokens, 0).is_empty());
+            assert!(kgram.k_gram_tokens(&tokens, 7).is_empty());
+        }
+    }
+
+    #[test]
+    fn default_token_k_gram() {
+        /// Only hashes bytes, like an implementation predating tokens
+        struct Bytes;
+        impl Kgram for Bytes {
+            fn k_gram(&self, data: &[u8], k: usize) -> Vec<u64> {
+                StdHashKgram.k_gram(data, k)
+            }
+        }
+
+        let tokens = [1, 2, 3, 1, 2, 3];
+        let result = Bytes.k_gram_tokens(&tokens, 3);
+        assert_eq!(result.len(), 4);
+        assert_eq!(result[0], result[3]);
+        assert_ne!(result[0], result[1]);
+        assert!(Bytes.k_gram_tokens(&tokens, 0).is_empty());
+        assert!(Bytes.k_gram_tokens(&tokens, 7).is_empty());
+    }
+
+    #[test]
+    fn rolling_token_k_gram() {
+        let r: RollingHashKgram<257, { u64::MAX }> = RollingHashKgram;
+        // byte values as tokens hash exactly like the bytes themselves
+        let text = "adorunrunrunadorunrun";
+        let tokens: Vec<u64> = text.bytes().map(u64::from).collect();
+        assert_eq!(r.k_gram_tokens(&tokens, 5), r.k_gram(text.as_bytes(), 5));
+
+        // rolling over large token ids gives the same hash as hashing each window anew
+        let tokens = [u64::MAX, u64::MAX - 1, 1 << 63, 42, u64::MAX, 7];
+        let rolled = r.k_gram_tokens(&tokens, 3);
+        let fresh: Vec<u64> = tokens
+            .windows(3)
+            .map(|window| r.k_gram_tokens(window, 3)[0])
+            .collect();
+        assert_eq!(rolled, fresh);
+    }
 }
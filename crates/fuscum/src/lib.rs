@@ -1,5 +1,10 @@
+mod error;
 pub mod fingerprint;
 pub mod hash;
 pub mod kgram;
+pub mod matching;
 pub mod preprocess;
+pub mod span;
 pub mod winnow;
+
+pub use error::{Error, Result};
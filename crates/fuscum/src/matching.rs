@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use typed_builder::TypedBuilder;
+
+use crate::fingerprint::FingerPrint;
+use crate::span::Location;
+
+#[derive(TypedBuilder, Debug, Clone)]
+pub struct MatchConfig {
+    /// the largest distance between two shared fingerprints, on either side,
+    /// for them to be merged into the same region.
+    /// Defaults to the winnowing guarantee threshold (`window_size + k`)
+    #[builder(default, setter(strip_option))]
+    pub gap: Option<usize>,
+    /// the minimum number of shared fingerprints a region must contain to be reported
+    #[builder(default = 1)]
+    pub min_fingerprints: usize,
+}
+
+impl Default for MatchConfig {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+/// One side of a matched region
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Region {
+    /// the positions covered in the preprocessed source
+    pub positions: Range<usize>,
+    /// the region of the original source
+    pub location: Location,
+}
+
+impl Region {
+    /// The number of preprocessed units (bytes) covered by the region
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+}
+
+/// A contiguous region shared between two fingerprinted sources
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    pub left: Region,
+    pub right: Region,
+    /// the number of shared fingerprints merged into this region
+    pub fingerprints: usize,
+}
+
+/// Shared fingerprint positions being merged into a region
+struct Chain {
+    first: (usize, usize),
+    last: (usize, usize),
+    count: usize,
+}
+
+/// Find the regions shared between two fingerprints, with the default configuration
+pub fn find_matches(left: &FingerPrint, right: &FingerPrint) -> Vec<Match> {
+    find_matches_with(left, right, &MatchConfig::default())
+}
+
+/// Find the regions shared between two fingerprints.
+///
+/// Positions of shared hashes are paired up and chained in order of
+/// appearance whenever both sides advance by no more than the configured gap.
+/// Overlapping chains are resolved in favor of the one with more fingerprints,
+/// and the remaining regions are returned in order of appearance in `left`.
+pub fn find_matches_with(
+    left: &FingerPrint,
+    right: &FingerPrint,
+    config: &MatchConfig,
+) -> Vec<Match> {
+    let gap = config
+        .gap
+        .unwrap_or_else(|| left.window_size().max(right.window_size()) + left.k().max(right.k()));
+
+    let mut positions: HashMap<u64, Vec<usize>> = HashMap::new();
+    for &(hash, position) in right.raw_fingerprint() {
+        positions.entry(hash).or_default().push(position);
+    }
+
+    let mut pairs: Vec<(usize, usize)> = left
+        .raw_fingerprint()
+        .iter()
+        .flat_map(|(hash, l)| {
+            positions
+                .get(hash)
+                .into_iter()
+                .flatten()
+                .map(move |&r| (*l, r))
+        })
+        .collect();
+    pairs.sort_unstable();
+    pairs.dedup();
+
+    let mut chains: Vec<Chain> = Vec::new();
+    let mut open: Vec<usize> = Vec::new();
+    for (l, r) in pairs {
+        // chains which can no longer be extended on the left side are closed
+        open.retain(|&c| l - chains[c].last.0 <= gap);
+        let extends = open.iter().copied().find(|&c| {
+            let (last_l, last_r) = chains[c].last;
+            l > last_l && r > last_r && r - last_r <= gap
+        });
+        match extends {
+            Some(c) => {
+                chains[c].last = (l, r);
+                chains[c].count += 1;
+            }
+            None => {
+                open.push(chains.len());
+                chains.push(Chain {
+                    first: (l, r),
+                    last: (l, r),
+                    count: 1,
+                });
+            }
+        }
+    }
+
+    chains.retain(|c| c.count >= config.min_fingerprints);
+    chains.sort_by(|a, b| b.count.cmp(&a.count).then(a.first.cmp(&b.first)));
+
+    let mut matches: Vec<Match> = Vec::new();
+    for chain in chains {
+        let l = chain.first.0..(chain.last.0 + left.k()).min(left.preprocessed_len());
+        let r = chain.first.1..(chain.last.1 + right.k()).min(right.preprocessed_len());
+        let overlaps = matches
+            .iter()
+            .any(|m| overlap(&m.left.positions, &l) || overlap(&m.right.positions, &r));
+        if overlaps {
+            continue;
+        }
+        let (Some(l_loc), Some(r_loc)) = (left.locate(l.clone()), right.locate(r.clone())) else {
+            continue;
+        };
+        matches.push(Match {
+            left: Region {
+                positions: l,
+                location: l_loc,
+            },
+            right: Region {
+                positions: r,
+                location: r_loc,
+            },
+            fingerprints: chain.count,
+        });
+    }
+
+    matches.sort_by_key(|m| m.left.positions.start);
+    matches
+}
+
+fn overlap(a: &Range<usize>, b: &Range<usize>) -> bool {
+    a.start < b.end && b.start < a.end
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fingerprint::{FingerPrintConfig, FingerPrintGenerator};
+    use crate::kgram::default_rolling_kgram;
+    use crate::preprocess::RegexPreprocessor;
+
+    fn generator() -> FingerPrintGenerator<RegexPreprocessor> {
+        FingerPrintGenerator {
+            config: FingerPrintConfig::builder().k(5).window_size(4).build(),
+            preprocessor: RegexPreprocessor::whitespace(),
+            kgram: Box::new(default_rolling_kgram()),
+        }
+    }
+
+    #[test]
+    fn shared_region() {
+        let shared = "the quick brown fox\njumps over the lazy dog\n";
+        let a = format!("one two three four five six\n{shared}");
+        let b = format!("{shared}seven eight nine ten eleven\n");
+
+        let gen = generator();
+        let matches = find_matches(&gen.generate(&a), &gen.generate(&b));
+        assert_eq!(matches.len(), 1);
+
+        let m = &matches[0];
+        assert!(m.fingerprints > 1);
+        assert_eq!(m.left.location.start.line, 2);
+        assert_eq!(m.left.location.end.line, 3);
+        assert_eq!(m.right.location.start.line, 1);
+        assert_eq!(m.right.location.end.line, 2);
+        assert_eq!(m.left.len(), m.right.len());
+    }
+
+    #[test]
+    fn disjoint_sources() {
+        let gen = generator();
+        let a = gen.generate("abcdefghijklmnopqrstuvwxyz");
+        let b = gen.generate("0123456789012345678901234567890");
+        assert!(find_matches(&a, &b).is_empty());
+    }
+
+    #[test]
+    fn min_fingerprints() {
+        let gen = generator();
+        let a = gen.generate("xxxxxxxxxxxxxxxx abcdefghij yyyyyyyyyyyyyyyyy");
+        let b = gen.generate("zzzzzzzzzzzzzzzz abcdefghij wwwwwwwwwwwwwwwww");
+        let config = MatchConfig::builder().min_fingerprints(usize::MAX).build();
+        assert!(!find_matches(&a, &b).is_empty());
+        assert!(find_matches_with(&a, &b, &config).is_empty());
+    }
+}
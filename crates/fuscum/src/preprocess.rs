@@ -1,4 +1,5 @@
 use std::borrow::Cow;
+use std::ops::Range;
 
 #[cfg(feature = "ast")]
 mod lang;
@@ -10,12 +11,168 @@ mod tree;
 pub use lang::*;
 pub use text::*;
 
+use crate::span::OffsetMap;
+
+/// The output of a preprocessor, along with where each byte of it
+/// came from in the original source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Preprocessed<'a> {
+    pub text: Cow<'a, str>,
+    pub offsets: OffsetMap,
+    /// the number of syntax errors found while parsing the source
+    pub errors: usize,
+}
+
+impl<'a> Preprocessed<'a> {
+    /// Wrap a source which was not altered by preprocessing
+    pub fn unchanged(src: &'a str) -> Self {
+        Self {
+            text: Cow::Borrowed(src),
+            offsets: OffsetMap::identity(src.len()),
+            errors: 0,
+        }
+    }
+}
+
+/// The source as a stream of tokens, each identified by the hash of its
+/// normalized text, along with where each token came from in the original source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tokenized {
+    pub tokens: Vec<u64>,
+    pub offsets: OffsetMap,
+    /// the number of syntax errors found while parsing the source
+    pub errors: usize,
+}
+
+impl Tokenized {
+    /// Split preprocessed text into words (runs of alphanumeric characters
+    /// and underscores) and single punctuation characters, skipping whitespace
+    pub fn lex(preprocessed: &Preprocessed) -> Self {
+        let text = &preprocessed.text;
+        let mut tokens = Vec::new();
+        let mut offsets = OffsetMap::new();
+        let mut chars = text.char_indices().peekable();
+        while let Some((start, c)) = chars.next() {
+            if c.is_whitespace() {
+                continue;
+            }
+            let mut end = start + c.len_utf8();
+            if is_word(c) {
+                while let Some(&(i, c)) = chars.peek().filter(|(_, c)| is_word(*c)) {
+                    end = i + c.len_utf8();
+                    chars.next();
+                }
+            }
+            tokens.push(token_id(&text[start..end]));
+            offsets.push(preprocessed.offsets.span(start..end).unwrap_or_default());
+        }
+        Self {
+            tokens,
+            offsets,
+            errors: preprocessed.errors,
+        }
+    }
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// A function or method of a source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Function {
+    pub name: String,
+    /// the range of the source the function spans
+    pub bytes: Range<usize>,
+}
+
+/// The identifier of a token with the given text, the 64-bit FNV-1a hash of
+/// it, which unlike the standard hasher is the same across Rust releases,
+/// so that fingerprints in an index stay comparable
+pub fn token_id(text: &str) -> u64 {
+    fnv1a(FNV_OFFSET, text.as_bytes())
+}
+
+pub(crate) const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+
+/// Continue a 64-bit FNV-1a hash with the given bytes
+pub(crate) fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
 pub trait Preprocessor {
-    fn preprocess<'a>(&self, src: &'a str) -> Cow<'a, str>;
+    /// Preprocess the source, keeping track of the original offsets
+    fn preprocess_mapped<'a>(&self, src: &'a str) -> Preprocessed<'a>;
+
+    fn preprocess<'a>(&self, src: &'a str) -> Cow<'a, str> {
+        self.preprocess_mapped(src).text
+    }
+
+    /// Split the source into tokens, by default by lexing the preprocessed text
+    fn tokenize(&self, src: &str) -> Tokenized {
+        Tokenized::lex(&self.preprocess_mapped(src))
+    }
+
+    /// Split the source into the shapes of its syntax subtrees with at least
+    /// `min_size` nodes, or `None` if the preprocessor does not parse the source
+    fn subtrees(&self, _src: &str, _min_size: usize) -> Option<Tokenized> {
+        None
+    }
+
+    /// The functions and methods of the source, ordered by where they start,
+    /// or none if the preprocessor does not parse the source
+    fn functions(&self, _src: &str) -> Vec<Function> {
+        Vec::new()
+    }
 }
 
 impl Preprocessor for Box<dyn Preprocessor> {
+    fn preprocess_mapped<'a>(&self, src: &'a str) -> Preprocessed<'a> {
+        self.as_ref().preprocess_mapped(src)
+    }
+
     fn preprocess<'a>(&self, src: &'a str) -> Cow<'a, str> {
         self.as_ref().preprocess(src)
     }
+
+    fn tokenize(&self, src: &str) -> Tokenized {
+        self.as_ref().tokenize(src)
+    }
+
+    fn subtrees(&self, src: &str, min_size: usize) -> Option<Tokenized> {
+        self.as_ref().subtrees(src, min_size)
+    }
+
+    fn functions(&self, src: &str) -> Vec<Function> {
+        self.as_ref().functions(src)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_ids_are_stable() {
+        // reference values of FNV-1a, which must never change
+        assert_eq!(token_id(""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(token_id("a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(token_id("foobar"), 0x8594_4171_f739_67e8);
+        assert_ne!(token_id("ab"), token_id("ba"));
+    }
+
+    #[test]
+    fn lex() {
+        let tokens = Tokenized::lex(&Preprocessed::unchanged("foo_1 = bar(2);"));
+        let expected: Vec<u64> = ["foo_1", "=", "bar", "(", "2", ")", ";"]
+            .into_iter()
+            .map(token_id)
+            .collect();
+        assert_eq!(tokens.tokens, expected);
+        assert_eq!(tokens.offsets.get(0), Some(0..5));
+        assert_eq!(tokens.offsets.get(2), Some(8..11));
+        assert_eq!(tokens.offsets.span(2..6), Some(8..14));
+    }
 }
@@ -1,5 +1,7 @@
 use crate::preprocess::tree::Tree;
-use crate::preprocess::Preprocessor;
+use crate::preprocess::{Function, Preprocessed, Preprocessor, Tokenized};
+use crate::span::OffsetMap;
+use ast_grep_language::LanguageExt;
 use std::borrow::Cow;
 use typed_builder::TypedBuilder;
 
@@ -7,23 +9,30 @@ macro_rules! impl_lang_preprocessor {
     (
         $name:ident,
         lang => $lang:expr,
-        ident => $identifier_token:expr,
-        string => $string_token:expr,
-        comment => $comment_token:expr
+        ident => [$($identifier_token:expr),+ $(,)?],
+        string => [$($string_token:expr),+ $(,)?],
+        comment => [$($comment_token:expr),+ $(,)?],
+        literal => [$($literal_token:expr),+ $(,)?],
+        function => [$($function_token:expr),+ $(,)?]
     ) => {
         #[derive(TypedBuilder)]
         #[builder(doc, field_defaults(default, setter(into)))]
         pub struct $name {
             #[builder(setter(
-                strip_option,
+                strip_option(fallback = subst_var_opt),
                 doc = "Substitute variable to a certain unified representation"
             ))]
             subst_var: Option<String>,
             #[builder(setter(
-                strip_option,
+                strip_option(fallback = subst_string_opt),
                 doc = "Substitute string to a certain unified representation"
             ))]
             subst_string: Option<String>,
+            #[builder(setter(
+                strip_option(fallback = subst_literal_opt),
+                doc = "Substitute numbers, chars, booleans and null to a certain unified representation"
+            ))]
+            subst_literal: Option<String>,
             #[builder(default = true, setter(doc = "Remove comments from the source code"))]
             remove_comments: bool,
         }
@@ -33,33 +42,88 @@ macro_rules! impl_lang_preprocessor {
                 Self {
                     subst_var: Some("v".to_string()),
                     subst_string: Some("\"s\"".to_string()),
+                    subst_literal: None,
                     remove_comments: true,
                 }
             }
         }
 
-        impl Preprocessor for $name {
-            fn preprocess<'a>(&self, src: &'a str) -> Cow<'a, str> {
+        impl $name {
+            /// Parse the source and apply the configured normalizations,
+            /// returning the tree along with the number of syntax errors
+            fn normalized(&self, src: &str) -> (Tree<impl LanguageExt + Copy>, usize) {
                 let mut tree = Tree::new(src, $lang);
+                let errors = tree.error_count();
 
                 if self.remove_comments {
-                    tree.remove_comments($comment_token);
+                    $(tree.remove_comments($comment_token);)+
+                }
+
+                if let Some(v) = &self.subst_var {
+                    $(tree.subst_ident($identifier_token, v);)+
+                }
+
+                // kinds enclosing others come first, e.g. concatenated strings,
+                // so that their parts are not replaced one by one
+                if let Some(v) = &self.subst_string {
+                    $(tree.subst_string($string_token, v);)+
                 }
 
-                self.subst_var
-                    .as_ref()
-                    .map(|v| tree.subst_ident($identifier_token, v));
+                if let Some(v) = &self.subst_literal {
+                    $(tree.subst_literal($literal_token, v);)+
+                }
 
-                self.subst_string
-                    .as_ref()
-                    .map(|v| tree.subst_string($string_token, v));
+                (tree, errors)
+            }
+        }
 
-                let src = tree
+        impl Preprocessor for $name {
+            fn preprocess_mapped<'a>(&self, src: &'a str) -> Preprocessed<'a> {
+                let (tree, errors) = self.normalized(src);
+                let mut text = String::with_capacity(tree.source().len());
+                let mut offsets = OffsetMap::new();
+                for (i, c) in tree
                     .source()
-                    .chars()
-                    .filter(|c| !c.is_whitespace())
-                    .collect::<String>();
-                Cow::Owned(src)
+                    .char_indices()
+                    .filter(|(_, c)| !c.is_whitespace())
+                {
+                    text.push(c);
+                    offsets.extend_from(tree.offsets(), i..i + c.len_utf8());
+                }
+                Preprocessed {
+                    text: Cow::Owned(text),
+                    offsets,
+                    errors,
+                }
+            }
+
+            fn tokenize(&self, src: &str) -> Tokenized {
+                let (tree, errors) = self.normalized(src);
+                let (tokens, offsets) = tree.tokens();
+                Tokenized {
+                    tokens,
+                    offsets,
+                    errors,
+                }
+            }
+
+            fn functions(&self, src: &str) -> Vec<Function> {
+                // names are taken from the original source, before any normalization
+                let tree = Tree::new(src, $lang);
+                let mut functions = Vec::new();
+                $(functions.extend(tree.functions($function_token));)+
+                functions.sort_by_key(|f| (f.bytes.start, std::cmp::Reverse(f.bytes.end)));
+                functions
+            }
+
+            fn subtrees(&self, src: &str, min_size: usize) -> Option<Tokenized> {
+                let (tree, errors) = self.normalized(src);
+                let (tokens, offsets) = tree.subtrees(min_size);
+                Some(Tokenized {
+                    tokens,
+                    offsets,
+                    errors,
+                })
             }
         }
     };
@@ -68,73 +132,238 @@ macro_rules! impl_lang_preprocessor {
 impl_lang_preprocessor!(
     PythonPreprocessor,
     lang => ast_grep_language::Python,
-    ident => "identifier",
-    string => "string",
-    comment => "comment"
+    ident => ["identifier"],
+    string => ["concatenated_string", "string"],
+    comment => ["comment"],
+    literal => ["integer", "float", "true", "false", "none"],
+    function => ["function_definition"]
 );
 
 impl_lang_preprocessor!(
     CPreprocessor,
     lang => ast_grep_language::C,
-    ident => "identifier",
-    string => "string_literal",
-    comment => "comment"
+    ident => ["identifier", "field_identifier"],
+    string => ["concatenated_string", "string_literal"],
+    comment => ["comment"],
+    literal => ["number_literal", "char_literal", "true", "false", "null"],
+    function => ["function_definition"]
 );
 
 impl_lang_preprocessor!(
     CppPreprocessor,
     lang => ast_grep_language::Cpp,
-    ident => "identifier",
-    string => "string_literal",
-    comment => "comment"
+    ident => ["identifier", "field_identifier"],
+    string => ["concatenated_string", "string_literal", "raw_string_literal"],
+    comment => ["comment"],
+    literal => ["user_defined_literal", "number_literal", "char_literal", "true", "false", "null"],
+    function => ["function_definition"]
 );
 
 impl_lang_preprocessor!(
     JavaScriptPreprocessor,
     lang => ast_grep_language::JavaScript,
-    ident => "identifier",
-    string => "string",
-    comment => "comment"
+    ident => ["identifier", "property_identifier", "shorthand_property_identifier"],
+    string => ["template_string", "string"],
+    comment => ["comment", "html_comment"],
+    literal => ["number", "true", "false", "null"],
+    function => [
+        "function_declaration",
+        "generator_function_declaration",
+        "method_definition",
+        "function_expression",
+        "generator_function",
+        "arrow_function",
+    ]
 );
 
 impl_lang_preprocessor!(
     TypeScriptPreprocessor,
     lang => ast_grep_language::TypeScript,
-    ident => "identifier",
-    string => "string",
-    comment => "comment"
+    ident => ["identifier", "property_identifier", "shorthand_property_identifier"],
+    string => ["template_string", "string"],
+    comment => ["comment", "html_comment"],
+    literal => ["number", "true", "false", "null"],
+    function => [
+        "function_declaration",
+        "generator_function_declaration",
+        "method_definition",
+        "function_expression",
+        "generator_function",
+        "arrow_function",
+    ]
 );
 
 impl_lang_preprocessor!(
     JavaPreprocessor,
     lang => ast_grep_language::Java,
-    ident => "identifier",
-    string => "string_literal",
-    comment => "comment"
+    ident => ["identifier"],
+    string => ["string_literal"],
+    comment => ["line_comment", "block_comment"],
+    literal => [
+        "decimal_integer_literal",
+        "hex_integer_literal",
+        "octal_integer_literal",
+        "binary_integer_literal",
+        "decimal_floating_point_literal",
+        "hex_floating_point_literal",
+        "character_literal",
+        "true",
+        "false",
+        "null_literal",
+    ],
+    function => ["method_declaration", "constructor_declaration"]
 );
 
 impl_lang_preprocessor!(
     GoPreprocessor,
     lang => ast_grep_language::Go,
-    ident => "identifier",
-    string => "interpreted_string_literal",
-    comment => "comment"
+    ident => ["identifier", "field_identifier"],
+    string => ["interpreted_string_literal", "raw_string_literal"],
+    comment => ["comment"],
+    literal => [
+        "int_literal",
+        "float_literal",
+        "imaginary_literal",
+        "rune_literal",
+        "true",
+        "false",
+        "nil",
+    ],
+    function => ["function_declaration", "method_declaration", "func_literal"]
 );
 
 impl_lang_preprocessor!(
     RustPreprocessor,
     lang => ast_grep_language::Rust,
-    ident => "identifier",
-    string => "string_literal",
-    comment => "line_comment"
+    ident => ["identifier", "field_identifier", "shorthand_field_identifier"],
+    string => ["string_literal", "raw_string_literal"],
+    comment => ["line_comment", "block_comment"],
+    literal => ["integer_literal", "float_literal", "char_literal", "boolean_literal"],
+    function => ["function_item"]
 );
 
 impl_lang_preprocessor!(
     RubyPreprocessor,
     lang => ast_grep_language::Ruby,
-    ident => "identifier",
-    string => "string",
-    comment => "comment"
+    ident => ["identifier", "instance_variable"],
+    string => ["chained_string", "string", "heredoc_body"],
+    comment => ["comment"],
+    literal => ["integer", "float", "character", "true", "false", "nil"],
+    function => ["method", "singleton_method"]
+);
+
+impl_lang_preprocessor!(
+    CSharpPreprocessor,
+    lang => ast_grep_language::CSharp,
+    ident => ["identifier"],
+    string => [
+        "interpolated_string_expression",
+        "string_literal",
+        "verbatim_string_literal",
+        "raw_string_literal",
+    ],
+    comment => ["comment"],
+    literal => [
+        "integer_literal",
+        "real_literal",
+        "character_literal",
+        "boolean_literal",
+        "null_literal",
+    ],
+    function => ["method_declaration", "constructor_declaration", "local_function_statement"]
+);
+
+impl_lang_preprocessor!(
+    KotlinPreprocessor,
+    lang => ast_grep_language::Kotlin,
+    ident => ["simple_identifier"],
+    string => ["string_literal"],
+    comment => ["line_comment", "multiline_comment"],
+    literal => [
+        "integer_literal",
+        "long_literal",
+        "hex_literal",
+        "bin_literal",
+        "unsigned_literal",
+        "real_literal",
+        "character_literal",
+        "boolean_literal",
+        "null_literal",
+    ],
+    function => ["function_declaration", "secondary_constructor"]
+);
+
+impl_lang_preprocessor!(
+    SwiftPreprocessor,
+    lang => ast_grep_language::Swift,
+    ident => ["simple_identifier"],
+    string => ["line_string_literal", "multi_line_string_literal", "raw_string_literal"],
+    comment => ["comment", "multiline_comment"],
+    literal => [
+        "integer_literal",
+        "hex_literal",
+        "oct_literal",
+        "bin_literal",
+        "real_literal",
+        "boolean_literal",
+    ],
+    function => ["function_declaration", "init_declaration", "deinit_declaration"]
+);
+
+impl_lang_preprocessor!(
+    PhpPreprocessor,
+    lang => ast_grep_language::Php,
+    ident => ["name"],
+    string => ["string", "encapsed_string", "heredoc", "nowdoc"],
+    comment => ["comment"],
+    literal => ["integer", "float", "boolean", "null"],
+    function => ["function_definition", "method_declaration"]
+);
+
+impl_lang_preprocessor!(
+    ScalaPreprocessor,
+    lang => ast_grep_language::Scala,
+    ident => ["identifier"],
+    string => ["interpolated_string_expression", "string"],
+    comment => ["comment", "block_comment"],
+    literal => [
+        "integer_literal",
+        "floating_point_literal",
+        "character_literal",
+        "boolean_literal",
+        "null_literal",
+    ],
+    function => ["function_definition"]
+);
+
+impl_lang_preprocessor!(
+    HaskellPreprocessor,
+    lang => ast_grep_language::Haskell,
+    ident => ["variable"],
+    string => ["string"],
+    comment => ["comment", "haddock"],
+    literal => ["integer", "float", "char"],
+    function => ["function", "bind"]
+);
+
+impl_lang_preprocessor!(
+    LuaPreprocessor,
+    lang => ast_grep_language::Lua,
+    ident => ["identifier"],
+    string => ["string"],
+    comment => ["comment"],
+    literal => ["number", "true", "false", "nil"],
+    function => ["function_declaration"]
+);
+
+impl_lang_preprocessor!(
+    BashPreprocessor,
+    lang => ast_grep_language::Bash,
+    ident => ["variable_name"],
+    string => ["string", "raw_string", "ansi_c_string"],
+    comment => ["comment"],
+    literal => ["number"],
+    function => ["function_definition"]
 );
 
 #[cfg(test)]
@@ -212,4 +441,176 @@ mod tests {
         let res = pp.preprocess(src);
         insta::assert_snapshot!(&format!("{src}\n\n\n{res}"))
     }
+
+    #[test]
+    fn csharp() {
+        let src = include_str!("../../../../fixtures/langs/csharp.cs");
+        let pp = CSharpPreprocessor::default();
+        let res = pp.preprocess(src);
+        insta::assert_snapshot!(&format!("{src}\n\n\n{res}"))
+    }
+
+    #[test]
+    fn kotlin() {
+        let src = include_str!("../../../../fixtures/langs/kotlin.kt");
+        let pp = KotlinPreprocessor::default();
+        let res = pp.preprocess(src);
+        insta::assert_snapshot!(&format!("{src}\n\n\n{res}"))
+    }
+
+    #[test]
+    fn swift() {
+        let src = include_str!("../../../../fixtures/langs/swift.swift");
+        let pp = SwiftPreprocessor::default();
+        let res = pp.preprocess(src);
+        insta::assert_snapshot!(&format!("{src}\n\n\n{res}"))
+    }
+
+    #[test]
+    fn php() {
+        let src = include_str!("../../../../fixtures/langs/php.php");
+        let pp = PhpPreprocessor::default();
+        let res = pp.preprocess(src);
+        insta::assert_snapshot!(&format!("{src}\n\n\n{res}"))
+    }
+
+    #[test]
+    fn scala() {
+        let src = include_str!("../../../../fixtures/langs/scala.scala");
+        let pp = ScalaPreprocessor::default();
+        let res = pp.preprocess(src);
+        insta::assert_snapshot!(&format!("{src}\n\n\n{res}"))
+    }
+
+    #[test]
+    fn haskell() {
+        let src = include_str!("../../../../fixtures/langs/haskell.hs");
+        let pp = HaskellPreprocessor::default();
+        let res = pp.preprocess(src);
+        insta::assert_snapshot!(&format!("{src}\n\n\n{res}"))
+    }
+
+    #[test]
+    fn lua() {
+        let src = include_str!("../../../../fixtures/langs/lua.lua");
+        let pp = LuaPreprocessor::default();
+        let res = pp.preprocess(src);
+        insta::assert_snapshot!(&format!("{src}\n\n\n{res}"))
+    }
+
+    #[test]
+    fn bash() {
+        let src = include_str!("../../../../fixtures/langs/bash.sh");
+        let pp = BashPreprocessor::default();
+        let res = pp.preprocess(src);
+        insta::assert_snapshot!(&format!("{src}\n\n\n{res}"))
+    }
+
+    #[test]
+    fn comment_and_string_styles() {
+        let rust = RustPreprocessor::default();
+        assert_eq!(
+            rust.preprocess("// greet\nlet x = \"hi\";"),
+            rust.preprocess("/* greet */\nlet x = r#\"hi\"#;"),
+        );
+
+        let go = GoPreprocessor::default();
+        assert_eq!(
+            go.preprocess("// greet\nvar x = \"hi\""),
+            go.preprocess("/* greet */\nvar x = `hi`"),
+        );
+
+        let js = JavaScriptPreprocessor::default();
+        assert_eq!(
+            js.preprocess("let x = 'hi' + name;"),
+            js.preprocess("let x = `hi ${name}` + name;"),
+        );
+
+        let python = PythonPreprocessor::default();
+        assert_eq!(
+            python.preprocess("x = 'hi'"),
+            python.preprocess("x = f'h{i}' 'i'"),
+        );
+    }
+
+    #[test]
+    fn literals() {
+        let python = PythonPreprocessor::builder().subst_literal("0").build();
+        assert_eq!(python.preprocess("x = 10\ny = True"), "x=0y=0");
+        assert_eq!(
+            python.preprocess("x = 10\ny = None"),
+            python.preprocess("x = 1.5\ny = False"),
+        );
+
+        let c = CPreprocessor::builder().subst_literal("0").build();
+        assert_eq!(
+            c.preprocess("char c = 'a'; int x = 10;"),
+            c.preprocess("char c = 'b'; int x = 0x1f;"),
+        );
+
+        // off unless asked for
+        let rust = RustPreprocessor::default();
+        assert_ne!(
+            rust.preprocess("let x = 10;"),
+            rust.preprocess("let x = 11;")
+        );
+    }
+
+    #[test]
+    fn functions() {
+        let src =
+            "class A:\n    def f(self):\n        pass\n\ndef g():\n    def h():\n        pass\n";
+        let functions = PythonPreprocessor::default().functions(src);
+        let names: Vec<_> = functions.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["f", "g", "h"]);
+        assert_eq!(
+            &src[functions[0].bytes.clone()],
+            "def f(self):\n        pass"
+        );
+
+        let src =
+            "static int *add(int a, int b) { return a + b; }\nint main() { return add(1, 2); }";
+        let functions = CPreprocessor::default().functions(src);
+        let names: Vec<_> = functions.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["add", "main"]);
+
+        let src = "class A { constructor() {} get(x) { return x; } }\nfunction* gen() {}";
+        let functions = JavaScriptPreprocessor::default().functions(src);
+        let names: Vec<_> = functions.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["constructor", "get", "gen"]);
+
+        let src = "const add = (a, b) => a + b;\nlet sq = x => x * x;\n\
+                   var f = function inner() {}, g = function* () {};\n[1].map(x => x);";
+        let functions = JavaScriptPreprocessor::default().functions(src);
+        let names: Vec<_> = functions.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["add", "sq", "inner", "g", "<anonymous>"]);
+        assert_eq!(&src[functions[0].bytes.clone()], "(a, b) => a + b");
+
+        let src = "const f = (a: number): number => a;";
+        let functions = TypeScriptPreprocessor::default().functions(src);
+        assert_eq!(functions[0].name, "f");
+
+        let src = "func main() {\n\tf := func(a int) int { return a }\n\
+                   \tvar n, g = 1, func() {}\n\tgo func() {}()\n}";
+        let functions = GoPreprocessor::default().functions(src);
+        let names: Vec<_> = functions.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["main", "f", "g", "<anonymous>"]);
+
+        let src = "fun add(a: Int, b: Int): Int {\n    return a + b\n}\n";
+        let functions = KotlinPreprocessor::default().functions(src);
+        assert_eq!(functions[0].name, "add");
+    }
+
+    #[test]
+    fn offsets_point_into_original_source() {
+        let src = "def foo(a):\n    # add one\n    return a + 1\n";
+        let res = PythonPreprocessor::default().preprocess_mapped(src);
+        assert_eq!(res.text, "defv(v):returnv+1");
+        assert_eq!(res.offsets.len(), res.text.len());
+        // `v` in place of `foo`
+        assert_eq!(res.offsets.get(3), Some(4..7));
+        // `return` on the third line, after the comment
+        let ret = src.find("return").unwrap();
+        assert_eq!(res.offsets.span(8..14), Some(ret..ret + 6));
+    }
 }
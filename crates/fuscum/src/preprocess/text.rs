@@ -1,13 +1,18 @@
 use regex::Regex;
 use std::borrow::Cow;
 
-use crate::preprocess::Preprocessor;
+use crate::preprocess::{Preprocessed, Preprocessor};
+use crate::span::OffsetMap;
 
 /// A no-op preprocessor, which just returns the input string unchanged.
 #[derive(Debug, Clone, Default)]
 pub struct NoPreprocessor();
 
 impl Preprocessor for NoPreprocessor {
+    fn preprocess_mapped<'a>(&self, src: &'a str) -> Preprocessed<'a> {
+        Preprocessed::unchanged(src)
+    }
+
     fn preprocess<'a>(&self, src: &'a str) -> std::borrow::Cow<'a, str> {
         Cow::Borrowed(src)
     }
@@ -28,6 +33,30 @@ impl RegexPreprocessor {
 }
 
 impl Preprocessor for RegexPreprocessor {
+    fn preprocess_mapped<'a>(&self, src: &'a str) -> Preprocessed<'a> {
+        let mut matches = self.regex.find_iter(src).peekable();
+        if matches.peek().is_none() {
+            return Preprocessed::unchanged(src);
+        }
+
+        let mut text = String::with_capacity(src.len());
+        let mut offsets = OffsetMap::new();
+        let mut start = 0;
+        for m in matches {
+            text.push_str(&src[start..m.start()]);
+            (start..m.start()).for_each(|i| offsets.push(i..i + 1));
+            start = m.end();
+        }
+        text.push_str(&src[start..]);
+        (start..src.len()).for_each(|i| offsets.push(i..i + 1));
+
+        Preprocessed {
+            text: Cow::Owned(text),
+            offsets,
+            errors: 0,
+        }
+    }
+
     fn preprocess<'a>(&self, src: &'a str) -> std::borrow::Cow<'a, str> {
         self.regex.replace_all(src, "")
     }
@@ -51,4 +80,14 @@ mod tests {
         let preprocessor = RegexPreprocessor::whitespace();
         assert_eq!(preprocessor.preprocess("hello world!\n"), "helloworld!");
     }
+
+    #[test]
+    fn whitespace_preprocessor_offsets() {
+        let preprocessor = RegexPreprocessor::whitespace();
+        let res = preprocessor.preprocess_mapped("ab c\n d");
+        assert_eq!(res.text, "abcd");
+        assert_eq!(res.offsets.get(2), Some(3..4));
+        assert_eq!(res.offsets.get(3), Some(6..7));
+        assert_eq!(res.offsets.span(1..3), Some(1..4));
+    }
 }
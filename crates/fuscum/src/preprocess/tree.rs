@@ -1,12 +1,19 @@
+use std::ops::Range;
+
 use ast_grep_core::matcher::KindMatcher;
 use ast_grep_core::source::Edit;
 use ast_grep_core::tree_sitter::StrDoc;
-use ast_grep_core::{AstGrep, Language};
+use ast_grep_core::{AstGrep, Language, Node};
 use ast_grep_language::LanguageExt;
 
+use crate::preprocess::{fnv1a, token_id, Function};
+use crate::span::OffsetMap;
+
 pub struct Tree<L: Language + LanguageExt> {
     lang: L,
     ag: AstGrep<StrDoc<L>>,
+    /// maps each byte of the current source back to the source the tree was created from
+    offsets: OffsetMap,
 }
 
 impl<L: Language + Copy + LanguageExt> Tree<L> {
@@ -14,32 +21,50 @@ impl<L: Language + Copy + LanguageExt> Tree<L> {
         Self {
             ag: lang.ast_grep(src.as_ref()),
             lang,
+            offsets: OffsetMap::identity(src.as_ref().len()),
         }
     }
 
-    pub fn apply_edit_helper(&mut self, edits: Vec<Edit<String>>) -> String {
+    /// The number of ERROR and MISSING nodes in the tree, i.e. the syntax
+    /// errors tree-sitter recovered from while parsing the current source
+    pub fn error_count(&self) -> usize {
+        self.ag
+            .root()
+            .dfs()
+            .filter(|n| n.is_error() || n.is_missing())
+            .count()
+    }
+
+    pub fn apply_edit_helper(&mut self, edits: Vec<Edit<String>>) -> (String, OffsetMap) {
         debug_assert_ne!(edits.len(), 0);
         let mut new_content = String::new();
+        let mut new_offsets = OffsetMap::new();
         let old_content = self.ag.root().root().get_text();
         let mut start = 0;
         for diff in edits {
             let range = diff.position..diff.position + diff.deleted_length;
             new_content.push_str(&old_content[start..range.start]);
+            new_offsets.extend_from(&self.offsets, start..range.start);
             let replacement = String::from_utf8(diff.inserted_text).unwrap();
             new_content.push_str(&replacement);
+            // every byte of the replacement stands for the whole replaced range
+            let replaced = self.offsets.span(range.clone()).unwrap_or_default();
+            (0..replacement.len()).for_each(|_| new_offsets.push(replaced.clone()));
             start = range.end;
         }
         // add trailing statements
         new_content.push_str(&old_content[start..]);
-        new_content
+        new_offsets.extend_from(&self.offsets, start..old_content.len());
+        (new_content, new_offsets)
     }
 
     pub fn apply_edits(&mut self, edits: Vec<Edit<String>>) -> &mut Self {
         match edits.len() {
             0 => self,
             _ => {
-                let new_content = self.apply_edit_helper(edits);
+                let (new_content, new_offsets) = self.apply_edit_helper(edits);
                 self.ag = self.lang.ast_grep(&new_content);
+                self.offsets = new_offsets;
                 self
             }
         }
@@ -68,9 +93,178 @@ impl<L: Language + Copy + LanguageExt> Tree<L> {
         self.apply_edits(edits)
     }
 
+    pub fn subst_literal<S: AsRef<str>>(&mut self, kind: S, to: &str) -> &mut Self {
+        let pat = KindMatcher::new(kind.as_ref(), self.lang);
+        let edits = self.ag.root().replace_all(&pat, to);
+        self.apply_edits(edits)
+    }
+
     pub fn source(&self) -> &str {
         self.ag.source()
     }
+
+    /// The leaves of the tree as tokens, along with where each of them came
+    /// from in the source the tree was created from
+    pub fn tokens(&self) -> (Vec<u64>, OffsetMap) {
+        let mut tokens = Vec::new();
+        let mut offsets = OffsetMap::new();
+        for node in self.ag.root().dfs().filter(|n| n.is_leaf()) {
+            let text = node.text();
+            let text = text.trim();
+            if text.is_empty() {
+                continue;
+            }
+            tokens.push(token_id(text));
+            offsets.push(self.offsets.span(node.range()).unwrap_or_default());
+        }
+        (tokens, offsets)
+    }
+
+    /// The shapes of the named subtrees with at least `min_size` named nodes,
+    /// in source order, along with where each of them came from in the source
+    /// the tree was created from.
+    ///
+    /// A shape only depends on the kinds of the nodes, including the operators
+    /// and keywords among their unnamed children, and the shapes of the named
+    /// children of a node are combined regardless of their order, so renamed
+    /// identifiers, changed literals, reordered statements and swapped branches
+    /// all keep the shapes of the subtrees involved, while `a * 2` and `a - 1`
+    /// do not share one.
+    pub fn subtrees(&self, min_size: usize) -> (Vec<u64>, OffsetMap) {
+        let mut shapes = Vec::new();
+        shape(&self.ag.root(), min_size, &mut shapes);
+        let mut hashes = Vec::new();
+        let mut offsets = OffsetMap::new();
+        for (hash, range) in shapes.into_iter().flatten() {
+            hashes.push(hash);
+            offsets.push(self.offsets.span(range).unwrap_or_default());
+        }
+        (hashes, offsets)
+    }
+
+    /// The nodes of the given kind as functions, named after their `name`
+    /// field, the innermost `declarator` field for C-like languages, the
+    /// variable an anonymous function is assigned to, or else their first
+    /// identifier child
+    pub fn functions<S: AsRef<str>>(&self, kind: S) -> Vec<Function> {
+        let pat = KindMatcher::new(kind.as_ref(), self.lang);
+        self.ag
+            .root()
+            .find_all(&pat)
+            .map(|m| {
+                let node = m.get_node();
+                Function {
+                    name: function_name(node).unwrap_or_else(|| "<anonymous>".to_string()),
+                    bytes: self.offsets.span(node.range()).unwrap_or_default(),
+                }
+            })
+            .collect()
+    }
+
+    /// The offsets of the current source in the source the tree was created from
+    pub fn offsets(&self) -> &OffsetMap {
+        &self.offsets
+    }
+}
+
+fn function_name<L: LanguageExt>(node: &Node<StrDoc<L>>) -> Option<String> {
+    if let Some(name) = node.field("name") {
+        return Some(name.text().to_string());
+    }
+    if let Some(declarator) = node.field("declarator") {
+        return match declarator.kind().ends_with("identifier") {
+            true => Some(declarator.text().to_string()),
+            false => function_name(&declarator),
+        };
+    }
+    if let Some(name) = binding_name(node) {
+        return Some(name);
+    }
+    // neither the parameter nor the body of an arrow function `x => x` is a name
+    let unnamed: Vec<usize> = ["parameter", "body"]
+        .into_iter()
+        .filter_map(|field| Some(node.field(field)?.node_id()))
+        .collect();
+    node.children()
+        .find(|child| child.kind().ends_with("identifier") && !unnamed.contains(&child.node_id()))
+        .map(|child| child.text().to_string())
+}
+
+/// The variable an anonymous function is declared as, e.g. `f` in
+/// `const f = () => {}` or `f := func() {}`
+fn binding_name<L: LanguageExt>(node: &Node<StrDoc<L>>) -> Option<String> {
+    let parent = node.parent()?;
+    match parent.kind().as_ref() {
+        "variable_declarator" => {
+            let value = parent.field("value")?;
+            let name = parent.field("name")?;
+            (value.node_id() == node.node_id()).then(|| name.text().to_string())
+        }
+        // Go pairs a list of names with a list of values by position
+        "expression_list" => {
+            let index = parent
+                .children()
+                .filter(|child| child.is_named())
+                .position(|child| child.node_id() == node.node_id())?;
+            let declaration = parent.parent()?;
+            let name = match declaration.kind().as_ref() {
+                "short_var_declaration" | "assignment_statement" => declaration
+                    .field("left")?
+                    .children()
+                    .filter(|child| child.is_named())
+                    .nth(index)?,
+                "var_spec" | "const_spec" => declaration.field_children("name").nth(index)?,
+                _ => return None,
+            };
+            Some(name.text().to_string())
+        }
+        _ => None,
+    }
+}
+
+/// Hash the shape of a named node, returning it along with the number of named
+/// nodes in its subtree, and record it in `shapes` if the subtree is large enough.
+/// Nodes are recorded in pre-order, so a slot is reserved before the children.
+fn shape<L: LanguageExt>(
+    node: &Node<StrDoc<L>>,
+    min_size: usize,
+    shapes: &mut Vec<Option<(u64, Range<usize>)>>,
+) -> Option<(u64, usize)> {
+    if !node.is_named() {
+        return None;
+    }
+    let slot = shapes.len();
+    shapes.push(None);
+
+    let mut size = 1;
+    let mut children = Vec::new();
+    let mut tokens = Vec::new();
+    for child in node.children() {
+        match shape(&child, min_size, shapes) {
+            Some((hash, child_size)) => {
+                children.push(hash);
+                size += child_size;
+            }
+            // operators, keywords and punctuation, in source order
+            None => tokens.push(token_id(&child.kind())),
+        }
+    }
+    children.sort_unstable();
+
+    // the size bucket keeps small and large subtrees apart should their
+    // hashes collide, as each size only shares a shape with similar sizes
+    let bucket = usize::BITS - size.leading_zeros();
+    let mut hash = fnv1a(token_id(&node.kind()), &bucket.to_le_bytes());
+    for part in [tokens, children] {
+        hash = fnv1a(hash, &part.len().to_le_bytes());
+        for value in part {
+            hash = fnv1a(hash, &value.to_le_bytes());
+        }
+    }
+    if size >= min_size {
+        shapes[slot] = Some((hash, node.range()));
+    }
+    Some((hash, size))
 }
 
 #[cfg(test)]
@@ -84,4 +278,58 @@ mod tests {
         tree.subst_ident("identifier", "v");
         assert_eq!(tree.source(), "def v(v, v, v):\n\tv = 1");
     }
+
+    #[test]
+    fn tokens() {
+        let src = "# hi\nfoo = bar";
+        let mut tree = Tree::new(src, Python);
+        tree.remove_comments("comment");
+        tree.subst_ident("identifier", "v");
+        let (tokens, offsets) = tree.tokens();
+        assert_eq!(tokens, vec![token_id("v"), token_id("="), token_id("v")]);
+        assert_eq!(offsets.get(0), Some(5..8));
+        assert_eq!(offsets.get(1), Some(9..10));
+        assert_eq!(offsets.get(2), Some(11..14));
+    }
+
+    #[test]
+    fn subtrees_ignore_order() {
+        let a = Tree::new("def f(a):\n\tx = a + 1\n\tprint(x)\n", Python);
+        let b = Tree::new("def g(b):\n\tprint(y)\n\ty = b + 2\n", Python);
+        let (a_shapes, a_offsets) = a.subtrees(3);
+        let (b_shapes, _) = b.subtrees(3);
+        assert_eq!(a_shapes.len(), a_offsets.len());
+        // the whole module comes first, and its shape survives the reordering
+        assert_eq!(a_shapes[0], b_shapes[0]);
+        assert_eq!(a_offsets.get(0), Some(0..a.source().len()));
+        let mut a_sorted = a_shapes.clone();
+        let mut b_sorted = b_shapes.clone();
+        a_sorted.sort_unstable();
+        b_sorted.sort_unstable();
+        assert_eq!(a_sorted, b_sorted);
+        // larger minimum sizes keep fewer subtrees
+        assert!(a.subtrees(6).0.len() < a_shapes.len());
+    }
+
+    #[test]
+    fn subtrees_keep_operators() {
+        let shapes = |src| Tree::new(src, Python).subtrees(1).0;
+        assert_eq!(shapes("x = a * 2\n"), shapes("y = b * 3\n"));
+        assert_ne!(shapes("x = a * 2\n"), shapes("x = a - 1\n"));
+        assert_ne!(shapes("x = a < 2\n"), shapes("x = a > 2\n"));
+        assert_ne!(shapes("while a:\n\tf()\n"), shapes("if a:\n\tf()\n"));
+    }
+
+    #[test]
+    fn offsets_follow_edits() {
+        let src = "# hi\nfoo = bar";
+        let mut tree = Tree::new(src, Python);
+        tree.remove_comments("comment");
+        tree.subst_ident("identifier", "v");
+        assert_eq!(tree.source(), "\nv = v");
+        assert_eq!(tree.offsets().len(), tree.source().len());
+        assert_eq!(tree.offsets().get(0), Some(4..5));
+        assert_eq!(tree.offsets().get(1), Some(5..8));
+        assert_eq!(tree.offsets().get(5), Some(11..14));
+    }
 }
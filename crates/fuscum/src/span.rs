@@ -0,0 +1,219 @@
+use std::ops::Range;
+
+/// Maps every unit of a preprocessed sequence back to the byte range
+/// of the original source it was derived from.
+///
+/// Units that survive preprocessing unchanged map to the single byte they
+/// came from, while units introduced by a substitution map to the whole
+/// range of the source they replaced.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OffsetMap {
+    ranges: Vec<(usize, usize)>,
+}
+
+impl OffsetMap {
+    /// Create an empty offset map
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create an offset map where unit `i` maps to byte `i` of the source
+    pub fn identity(len: usize) -> Self {
+        Self {
+            ranges: (0..len).map(|i| (i, i + 1)).collect(),
+        }
+    }
+
+    /// Append a unit derived from the given range of the original source
+    pub fn push(&mut self, range: Range<usize>) {
+        self.ranges.push((range.start, range.end));
+    }
+
+    /// Append every unit of `units` from another offset map
+    pub fn extend_from(&mut self, other: &OffsetMap, units: Range<usize>) {
+        self.ranges.extend_from_slice(&other.ranges[units]);
+    }
+
+    /// The number of units in the map
+    pub fn len(&self) -> usize {
+        self.ranges.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// The range of the original source a single unit was derived from
+    pub fn get(&self, unit: usize) -> Option<Range<usize>> {
+        self.ranges.get(unit).map(|&(start, end)| start..end)
+    }
+
+    /// The range of the original source covered by a contiguous run of units.
+    ///
+    /// An empty run of units maps to an empty range at the position
+    /// of the unit it starts at (or the end of the source, if there is none).
+    pub fn span(&self, units: Range<usize>) -> Option<Range<usize>> {
+        if units.end > self.ranges.len() || units.start > units.end {
+            return None;
+        }
+        if units.is_empty() {
+            let at = match self.ranges.get(units.start) {
+                Some(&(start, _)) => start,
+                None => self.ranges.last().map_or(0, |&(_, end)| end),
+            };
+            return Some(at..at);
+        }
+        let run = &self.ranges[units];
+        let start = run.iter().map(|&(start, _)| start).min()?;
+        let end = run.iter().map(|&(_, end)| end).max()?;
+        Some(start..end)
+    }
+}
+
+/// A 1-based line and column (in bytes) within a source
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// A region of the original source, as a byte range and line/column span
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Location {
+    pub bytes: Range<usize>,
+    pub start: Position,
+    pub end: Position,
+}
+
+/// Resolves byte offsets of a source into line/column positions
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LineIndex {
+    line_starts: Vec<usize>,
+    /// the bytes of every character encoded in more than one byte, in order
+    wide_chars: Vec<Range<usize>>,
+    len: usize,
+}
+
+impl LineIndex {
+    pub fn new(src: &str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(src.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        let wide_chars = src
+            .char_indices()
+            .filter(|(_, c)| !c.is_ascii())
+            .map(|(i, c)| i..i + c.len_utf8())
+            .collect();
+        Self {
+            line_starts,
+            wide_chars,
+            len: src.len(),
+        }
+    }
+
+    /// The number of lines in the source
+    pub fn lines(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    /// The position of a byte offset, clamped to the end of the source
+    pub fn position(&self, offset: usize) -> Position {
+        let offset = offset.min(self.len);
+        let line = self.line_starts.partition_point(|&start| start <= offset) - 1;
+        Position {
+            line: line + 1,
+            column: offset - self.line_starts[line] + 1,
+        }
+    }
+
+    /// Resolve a byte range into a location, widened to whole characters so
+    /// that the source can be sliced with it.
+    /// The end position points at the last byte of the range, so that a
+    /// range ending with a newline does not spill over to the next line.
+    pub fn locate(&self, bytes: Range<usize>) -> Location {
+        let bytes = self.char_start(bytes.start)..self.char_end(bytes.end);
+        let start = self.position(bytes.start);
+        let end = self.position(bytes.end.saturating_sub(1).max(bytes.start));
+        Location { bytes, start, end }
+    }
+
+    /// The start of the character the byte at `offset` belongs to
+    fn char_start(&self, offset: usize) -> usize {
+        let i = self.wide_chars.partition_point(|c| c.end <= offset);
+        match self.wide_chars.get(i) {
+            Some(c) if c.start < offset => c.start,
+            _ => offset,
+        }
+    }
+
+    /// The end of the character the byte before `offset` belongs to
+    fn char_end(&self, offset: usize) -> usize {
+        let i = self.wide_chars.partition_point(|c| c.end <= offset);
+        match self.wide_chars.get(i) {
+            Some(c) if c.start < offset => c.end,
+            _ => offset,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identity_span() {
+        let map = OffsetMap::identity(10);
+        assert_eq!(map.span(2..5), Some(2..5));
+        assert_eq!(map.span(3..3), Some(3..3));
+        assert_eq!(map.span(10..10), Some(10..10));
+        assert_eq!(map.span(8..11), None);
+    }
+
+    #[test]
+    fn substituted_span() {
+        let mut map = OffsetMap::new();
+        // "v" standing for "foo", followed by "=1"
+        map.push(0..3);
+        map.push(4..5);
+        map.push(6..7);
+        assert_eq!(map.get(0), Some(0..3));
+        assert_eq!(map.span(0..2), Some(0..5));
+        assert_eq!(map.span(1..3), Some(4..7));
+    }
+
+    #[test]
+    fn line_index() {
+        let index = LineIndex::new("ab\ncd\n\nef");
+        assert_eq!(index.lines(), 4);
+        assert_eq!(index.position(0), Position { line: 1, column: 1 });
+        assert_eq!(index.position(2), Position { line: 1, column: 3 });
+        assert_eq!(index.position(3), Position { line: 2, column: 1 });
+        assert_eq!(index.position(7), Position { line: 4, column: 1 });
+        assert_eq!(index.position(100), Position { line: 4, column: 3 });
+
+        let loc = index.locate(3..6);
+        assert_eq!(loc.start, Position { line: 2, column: 1 });
+        assert_eq!(loc.end, Position { line: 2, column: 3 });
+    }
+
+    #[test]
+    fn locate_whole_chars() {
+        let src = "aé€b\nü";
+        let lines = LineIndex::new(src);
+        // inside `é` up to inside `€`
+        let location = lines.locate(2..4);
+        assert_eq!(location.bytes, 1..6);
+        assert_eq!(&src[location.bytes], "é€");
+        // boundaries are kept
+        assert_eq!(lines.locate(1..3).bytes, 1..3);
+        assert_eq!(lines.locate(6..7).bytes, 6..7);
+        assert_eq!(lines.locate(3..3).bytes, 3..3);
+        // the last character
+        let location = lines.locate(9..10);
+        assert_eq!(location.bytes, 8..10);
+        assert_eq!((location.start.line, location.end.line), (2, 2));
+    }
+}
@@ -0,0 +1,10 @@
+#!/usr/bin/env bash
+# A Bash comment
+
+greet() {
+    local message="Hello, $1"
+    echo "$message"
+}
+
+user_name='World'
+greet "$user_name"
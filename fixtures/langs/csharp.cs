@@ -0,0 +1,20 @@
+// A C# comment
+using System;
+
+class Person
+{
+    public string Name;
+
+    /* greet someone */
+    static string Greet(string name)
+    {
+        var message = "Hello, " + name;
+        return message;
+    }
+
+    static void Main()
+    {
+        var userName = "World";
+        Console.WriteLine(Greet(userName));
+    }
+}
@@ -0,0 +1,12 @@
+-- A Haskell comment
+module Main where
+
+data Person = Person { name :: String, age :: Int }
+
+{- greet someone -}
+greet :: String -> String
+greet userName = message
+  where message = "Hello, " ++ userName
+
+main :: IO ()
+main = putStrLn (greet "World")
@@ -0,0 +1,13 @@
+// A Kotlin comment
+data class Person(val name: String, val age: Int)
+
+/* greet someone */
+fun greet(name: String): String {
+    val message = "Hello, " + name
+    return message
+}
+
+fun main() {
+    val userName = "World"
+    println(greet(userName))
+}
@@ -0,0 +1,11 @@
+-- A Lua comment
+local Person = { name = "", age = 0 }
+
+--[[ greet someone ]]
+local function greet(name)
+    local message = "Hello, " .. name
+    return message
+end
+
+local userName = "World"
+print(greet(userName))
@@ -0,0 +1,16 @@
+<?php
+// A PHP comment
+class Person
+{
+    public $name;
+}
+
+/* greet someone */
+function greet($name)
+{
+    $message = "Hello, " . $name;
+    return $message;
+}
+
+$userName = 'World';
+echo greet($userName);
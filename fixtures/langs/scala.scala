@@ -0,0 +1,15 @@
+// A Scala comment
+case class Person(name: String, age: Int)
+
+object Main {
+  /* greet someone */
+  def greet(name: String): String = {
+    val message = "Hello, " + name
+    message
+  }
+
+  def main(args: Array[String]): Unit = {
+    val userName = "World"
+    println(greet(userName))
+  }
+}
@@ -0,0 +1,14 @@
+// A Swift comment
+struct Person {
+    var name: String
+    var age: Int
+}
+
+/* greet someone */
+func greet(name: String) -> String {
+    let message = "Hello, " + name
+    return message
+}
+
+let userName = "World"
+print(greet(name: userName))